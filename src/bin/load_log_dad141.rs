@@ -1,7 +1,6 @@
 use chrono::prelude::*;
 use load_lpp::load_log_dad141::*;
-use load_lpp::ERROR_FLT_PARSE;
-use load_lpp::{ERROR_STR_GENERAL, ERROR_STR_INVALID, ERROR_STR_NONE, ERROR_STR_SKIPPED};
+use load_lpp::ErrorCodes;
 use std::convert::TryInto;
 use std::io::prelude::*;
 use std::io::Error;
@@ -10,11 +9,27 @@ use std::time::Duration;
 
 fn main() {
     let timeout: Duration = Duration::new(15, 0); // seconds, nanoseconds
-    let connection_retry: Duration = Duration::new(30, 0); // seconds, nanoseconds
+    let connection_retry_base: Duration = Duration::new(5, 0); // seconds, nanoseconds
+    let connection_retry_max: Duration = Duration::new(300, 0); // seconds, nanoseconds
     let write_read_pause: Duration = Duration::new(2, 0); // seconds, nanoseconds
 
     // get CLI arguments
-    let (csv_name, ip, port, mut tcmd_str, minutes, delay, verbose) = parse_cli_log();
+    let LogCli {
+        csv_name,
+        ip,
+        port,
+        mut tcmd_str,
+        minutes,
+        delay,
+        prefix_len,
+        simulate,
+        max_samples,
+        duration,
+        fsync,
+        status_file,
+        error_base,
+        verbose,
+    } = parse_cli_log();
 
     if verbose {
         println!("csv_name {}", csv_name);
@@ -23,6 +38,13 @@ fn main() {
         println!("tcmd_str {}", tcmd_str);
         println!("minutes {}", minutes);
         println!("delay {}", delay);
+        println!("prefix_len {:?}", prefix_len);
+        println!("simulate {}", simulate);
+        println!("max_samples {:?}", max_samples);
+        println!("duration {:?}", duration);
+        println!("fsync {}", fsync);
+        println!("status_file {:?}", status_file);
+        println!("error_base {}", error_base);
     }
 
     // Init connection with a closure, which can later be used to refresh the connection if needed.
@@ -44,8 +66,15 @@ fn main() {
         connection.set_nodelay(true)?;
         Ok(connection)
     };
-    let mut connection = init_connection().expect("could not initiate the connection");
-    println!("connected to socket {}", socket.to_string());
+    // no connection when simulating the readings
+    let mut connection: Option<TcpStream> = if simulate {
+        println!("simulating the readings, no connection to socket {}", socket);
+        None
+    } else {
+        let connection = init_connection().expect("could not initiate the connection");
+        println!("connected to socket {}", socket.to_string());
+        Some(connection)
+    };
 
     // wait for delay if any
     if delay != 0 as u64 {
@@ -61,6 +90,8 @@ fn main() {
         .expect("bug on the telnet the command");
 
     let mut csvfile = prepare_csvfile(&csv_name);
+    let codes = ErrorCodes::from_base(error_base);
+    let skipped_str = codes.to_str(codes.skipped);
 
     // datetime
     let minutes_duration: chrono::Duration = chrono::Duration::minutes(minutes as i64);
@@ -74,6 +105,10 @@ fn main() {
         dtr_str, dtr_next_str, minutes
     );
 
+    // optional end of the logging, otherwise forever
+    let dtr_end: Option<DateTime<Local>> =
+        duration.map(|d| dtr + chrono::Duration::minutes(d as i64));
+
     // wait for the starting time
     let mut wait = dtr - Local::now();
     let mut sleep_duration = wait
@@ -85,51 +120,47 @@ fn main() {
     // init mut variables for tcp logging
     let mut connection_ok = true;
     let mut buffer = [0; 32];
-    let mut raw_reading: &str;
+    let mut raw_reading: String;
     let mut w: f64;
+    let mut samples: u64 = 0;
 
     loop {
-        match connection.read(&mut buffer) {
-            Ok(b) if b > 0 => println!("warning, found non-empty queue with length: {}", b),
-            _ => {}
-        }
+        if let Some(connection) = connection.as_mut() {
+            match connection.read(&mut buffer) {
+                Ok(b) if b > 0 => println!("warning, found non-empty queue with length: {}", b),
+                _ => {}
+            }
 
-        match connection.write(&tcmd) {
-            Ok(b) if b == 3 => {}
-            _ => println!("warning, failed to write command"),
-        }
+            match connection.write(&tcmd) {
+                Ok(b) if b == 3 => {}
+                _ => println!("warning, failed to write command"),
+            }
 
-        // a short delay before reading the logger response
-        std::thread::sleep(write_read_pause);
+            // a short delay before reading the logger response
+            std::thread::sleep(write_read_pause);
 
-        raw_reading = match connection.read(&mut buffer) {
-            Ok(0) => {
-                println!("{} no data", dtr_str);
-                connection_ok = false;
-                ERROR_STR_NONE
-            }
-            Ok(u) => match std::str::from_utf8(&buffer[0..u]) {
-                Ok(s) => s.trim_end(),
+            (w, raw_reading) = match connection.read(&mut buffer) {
+                Ok(u) => decode_reading(&buffer[0..u], prefix_len, &codes),
                 Err(e) => {
                     println!("{} IO error, {}", dtr_str, e);
                     connection_ok = false;
-                    ERROR_STR_INVALID
+                    (codes.general, codes.to_str(codes.general))
                 }
-            },
-            Err(e) => {
-                println!("{} IO error, {}", dtr_str, e);
-                connection_ok = false;
-                ERROR_STR_GENERAL
-            }
-        };
-
-        w = raw_reading
-            .get(2..)
-            .map(|s| s.parse().ok())
-            .flatten()
-            .unwrap_or(ERROR_FLT_PARSE);
+            };
+        } else {
+            let reply = simulate_reading(dtr, tcmd_str.trim_end());
+            (w, raw_reading) = decode_reading(reply.as_bytes(), prefix_len, &codes);
+        }
+        if w == codes.none {
+            println!("{} no data", dtr_str);
+            connection_ok = false;
+        } else if w == codes.invalid {
+            println!("{} IO error, invalid UTF-8 reply", dtr_str);
+            connection_ok = false;
+        }
 
-        match write!(&mut csvfile, "{},{},{}\n", dtr_str, w, raw_reading) {
+        let line = format!("{},{},{}\n", dtr_str, w, raw_reading);
+        match write_csv_line(&mut csvfile, &line, fsync) {
             Ok(_) => {
                 if verbose {
                     println!(
@@ -143,46 +174,86 @@ fn main() {
                 dtr_str, w, csv_name, raw_reading, e
             ),
         }
+        samples += 1;
 
-        // recover connection
+        // update the status only on success, a stale status signals trouble
+        let reading_ok = connection_ok & !codes.is_error_code(w);
+        match &status_file {
+            Some(f) if reading_ok => {
+                let status = LoggerStatus {
+                    datetime: &dtr_str,
+                    load_kg: w,
+                    connected: connection.is_some(),
+                };
+                if let Err(e) = write_status_file(f, &status) {
+                    println!("{}, could not write status file {}, error {}", dtr_str, f, e);
+                }
+            }
+            _ => {}
+        }
+
+        // stop after the last reading, no need to recover the connection
+        if logging_done(samples, max_samples, dtr_next, dtr_end) {
+            break;
+        }
+
+        // recover connection, retrying with exponential backoff and jitter
+        let mut attempt: u32 = 0;
         while connection_ok == false {
             println!("trying to refresh the connection");
             match init_connection() {
                 Ok(c) => {
                     println!("connection successful, resume logging");
-                    connection = c;
+                    connection = Some(c);
                     connection_ok = true;
                 }
                 Err(e) => {
-                    println!("connection failed, error {}, trying again ...", e);
-                    std::thread::sleep(connection_retry);
+                    let jitter = Local::now().timestamp_subsec_nanos() as f64 / 1e9;
+                    let retry = reconnect_backoff(
+                        attempt,
+                        connection_retry_base,
+                        connection_retry_max,
+                        jitter,
+                    );
+                    println!(
+                        "connection failed, error {}, trying again in {} s ...",
+                        e,
+                        retry.as_secs()
+                    );
+                    std::thread::sleep(retry);
+                    attempt = attempt.saturating_add(1);
                 }
             }
         }
 
         // recover datetime
-        while dtr_next <= Local::now() {
+        while (dtr_next <= Local::now()) & !logging_done(samples, max_samples, dtr_next, dtr_end) {
             println!(
                 "skipping next reading at {} because it has already passed",
                 dtr_next_str
             );
-            match write!(&mut csvfile, "{},{}\n", dtr_next_str, ERROR_STR_SKIPPED) {
+            // as the other errors, the code as load and its string as raw reading
+            let line = format!("{},{},{}\n", dtr_next_str, codes.skipped, skipped_str);
+            match write_csv_line(&mut csvfile, &line, fsync) {
                 Ok(_) => {
                     println!(
                         "datetime {}, wrote skipped value {} to file {}",
-                        dtr_next_str, ERROR_STR_SKIPPED, csv_name,
+                        dtr_next_str, skipped_str, csv_name,
                     );
                 }
                 Err(e) => {
                     println!(
                         "datetime {}, could not write skipped value {} to file {}, error {}",
-                        dtr_next_str, ERROR_STR_SKIPPED, csv_name, e
+                        dtr_next_str, skipped_str, csv_name, e
                     );
                 }
             }
             dtr_next = dtr_next + minutes_duration;
             dtr_next_str = dtr_next.to_rfc3339_opts(SecondsFormat::Secs, false);
         }
+        if logging_done(samples, max_samples, dtr_next, dtr_end) {
+            break;
+        }
 
         // wait for the next loop
         wait = dtr_next - Local::now();
@@ -194,4 +265,9 @@ fn main() {
         dtr_str = dtr.to_rfc3339_opts(SecondsFormat::Secs, false);
         dtr_next_str = dtr_next.to_rfc3339_opts(SecondsFormat::Secs, false);
     }
+
+    match csvfile.flush() {
+        Ok(_) => println!("logging done, {} reading(s) written to {}", samples, csv_name),
+        Err(e) => println!("could not flush file {}, error {}", csv_name, e),
+    }
 }
@@ -2,13 +2,39 @@ use load_lpp::load_plot::parse_cli;
 use load_lpp::TimeLoad;
 
 fn main() {
-    let (csvin, svgout) = parse_cli();
-    println!(
-        "read data from {} and plot to {}",
-        csvin.to_str().unwrap(),
-        svgout.to_str().unwrap()
-    );
-    let tw = TimeLoad::from_csv(csvin);
+    let (csvin, svgout, y_range, show, quiet) = parse_cli();
+    if !quiet {
+        eprintln!(
+            "read data from {} and plot to {}",
+            csvin.to_str().unwrap(),
+            svgout.to_str().unwrap()
+        );
+    }
+    match TimeLoad::validate_csv(&csvin) {
+        Ok(info) if info.datetime_format.is_some() => {
+            eprintln!(
+                "error, {} has datetimes in {}, expected RFC 3339",
+                csvin.to_str().unwrap(),
+                info.datetime_format.unwrap()
+            );
+            std::process::exit(1);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("error, {} is not a load time series: {}", csvin.to_str().unwrap(), e);
+            std::process::exit(1);
+        }
+    }
+    let tw = match TimeLoad::from_csv_auto(&csvin) {
+        Ok(tw) => tw,
+        Err(e) => {
+            eprintln!("error, could not read {}: {}", csvin.to_str().unwrap(), e);
+            std::process::exit(1);
+        }
+    };
     // tw.plot_datetime(svgout).unwrap();
-    tw.plotly_plot_datetime(svgout).unwrap();
+    if let Err(e) = tw.plotly_plot_datetime_with_range(&svgout, show, y_range) {
+        eprintln!("error, could not plot to {}: {}", svgout.to_str().unwrap(), e);
+        std::process::exit(1);
+    }
 }
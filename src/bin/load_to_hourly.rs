@@ -2,13 +2,43 @@ use load_lpp::load_to_hourly::parse_cli;
 use load_lpp::TimeLoad;
 
 fn main() {
-    let (csvin, csvout) = parse_cli();
-    println!(
-        "read data from {} and plot to {}",
-        csvin.to_str().unwrap(),
-        csvout.to_str().unwrap()
-    );
-    let tw = TimeLoad::from_csv(csvin);
-    let htw = tw.to_hourly().expect("empty data set");
+    let (csvin, csvout, agg, nan, time_weighted, nominal_step, quiet) = parse_cli();
+    if !quiet {
+        eprintln!(
+            "read data from {} and plot to {}",
+            csvin.to_str().unwrap(),
+            csvout.to_str().unwrap()
+        );
+    }
+    match TimeLoad::validate_csv(&csvin) {
+        Ok(info) if info.datetime_format.is_some() => {
+            eprintln!(
+                "error, {} has datetimes in {}, expected RFC 3339",
+                csvin.to_str().unwrap(),
+                info.datetime_format.unwrap()
+            );
+            std::process::exit(1);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("error, {} is not a load time series: {}", csvin.to_str().unwrap(), e);
+            std::process::exit(1);
+        }
+    }
+    let tw = match TimeLoad::from_csv_auto(&csvin) {
+        Ok(tw) => tw,
+        Err(e) => {
+            eprintln!("error, could not read {}: {}", csvin.to_str().unwrap(), e);
+            std::process::exit(1);
+        }
+    };
+    let step = chrono::Duration::hours(1);
+    let htw = if time_weighted {
+        let nominal_step = nominal_step.map(chrono::Duration::minutes);
+        tw.to_interval_time_weighted(step, nominal_step, nan)
+    } else {
+        tw.downsample_with_nan_policy(step, agg, nan)
+    }
+    .expect("empty data set");
     htw.to_csv(csvout)
 }
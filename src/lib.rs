@@ -4,13 +4,17 @@ extern crate test;
 pub use crate::utils::*;
 use chrono::prelude::*;
 // use plotters::prelude::*;
+use std::collections::HashMap;
+use std::error::Error;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use plotly::{Plot, Scatter};
+use plotly::{ImageFormat, Plot, Scatter};
+use rayon::prelude::*;
 use plotly::layout::{Axis, BarMode, BoxMode, Layout, Margin};
 use plotly::common::{Title, Font };
 use plotly::color::Rgba;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub mod load_log_dad141;
 pub mod load_plot;
@@ -29,6 +33,90 @@ pub const ERROR_FLT_NONE: f64 = 999998.;
 pub const ERROR_FLT_INVALID: f64 = 999997.;
 pub const ERROR_FLT_SKIPPED: f64 = 999996.;
 pub const ERROR_FLT_PARSE: f64 = 999995.;
+/// Number of data rows parsed by TimeLoad::validate_csv.
+pub const CSV_SAMPLE_ROWS: usize = 10;
+/// Datetime formats without offset recognized by TimeLoad::validate_csv, besides RFC 3339.
+pub const CSV_DATETIME_FORMATS: [&str; 5] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%m/%d/%Y %H:%M:%S",
+    "%m/%d/%Y %H:%M",
+];
+
+/// Maximum number of points of each plotted time series, see downsample_for_plot.
+pub const PLOT_MAX_POINTS: usize = 10000;
+/// Padding of the automatic y range of the plots, as percentage of the load range.
+pub const PLOT_Y_PAD_PCT: f64 = 5.;
+
+/// The kinds of errors recorded by the logger, see ErrorCodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    General,
+    None,
+    Invalid,
+    Skipped,
+    Parse,
+}
+
+/// The reserved values that the logger writes instead of a reading, one per error kind.
+/// The default is the ERROR_FLT_* scheme, see from_base to move it above the expected loads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorCodes {
+    pub general: f64,
+    pub none: f64,
+    pub invalid: f64,
+    pub skipped: f64,
+    pub parse: f64,
+}
+
+impl Default for ErrorCodes {
+    fn default() -> Self {
+        ErrorCodes::from_base(999990.)
+    }
+}
+
+impl ErrorCodes {
+    /// The codes from base + 9 (general) down to base + 5 (parse), as the default from 999990.
+    pub fn from_base(base: f64) -> Self {
+        ErrorCodes {
+            general: base + 9.,
+            none: base + 8.,
+            invalid: base + 7.,
+            skipped: base + 6.,
+            parse: base + 5.,
+        }
+    }
+
+    pub fn is_error_code(&self, v: f64) -> bool {
+        [self.general, self.none, self.invalid, self.skipped, self.parse].contains(&v)
+    }
+
+    /// The kind of error of the value, None if it is not an error code.
+    pub fn kind(&self, v: f64) -> Option<ErrorKind> {
+        match v {
+            v if v == self.general => Some(ErrorKind::General),
+            v if v == self.none => Some(ErrorKind::None),
+            v if v == self.invalid => Some(ErrorKind::Invalid),
+            v if v == self.skipped => Some(ErrorKind::Skipped),
+            v if v == self.parse => Some(ErrorKind::Parse),
+            _ => None,
+        }
+    }
+
+    /// The code as written in the raw reading column, e.g., E+999999.
+    pub fn to_str(&self, code: f64) -> String {
+        format!("E{:+}.", code)
+    }
+
+    /// Whether the string is an error code as written in the raw reading column, see to_str,
+    /// i.e., E, the signed integer code, and a trailing dot, whatever the base of the codes.
+    pub fn is_code_str(s: &str) -> bool {
+        s.strip_prefix('E')
+            .and_then(|c| c.strip_suffix('.'))
+            .is_some_and(|c| c.starts_with(['+', '-']) && c[1..].parse::<u64>().is_ok())
+    }
+}
 
 /// The main struct for the load time series.
 #[derive(Debug, Clone)]
@@ -37,6 +125,337 @@ pub struct TimeLoad {
     pub load: Vec<f64>,
 }
 
+/// How to replace the values within a bad daily time interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BadIntervalMode {
+    Nan,
+    LinearInterpolate,
+}
+
+/// Which load to keep for the duplicate datetimes, see dedup_timestamps.
+/// Average is the mean of the duplicates, NAN if any of them is NAN (see mean_or_nan).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DupPolicy {
+    KeepFirst,
+    KeepLast,
+    Average,
+}
+
+/// How to aggregate the loads of each time step when downsampling.
+/// Range is the max minus the min, e.g., for the load changes within the step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregator {
+    Mean,
+    Median,
+    Min,
+    Max,
+    Range,
+    Sum,
+    First,
+    Last,
+}
+
+impl Aggregator {
+    /// Aggregate the loads, NAN if they are empty or contain a NAN, as for mean_or_nan.
+    pub fn aggregate(&self, v: &Vec<f64>) -> f64 {
+        if v.is_empty() || v.iter().any(|f| f.is_nan()) {
+            return f64::NAN;
+        }
+        match self {
+            Aggregator::Mean => mean_or_nan(v),
+            Aggregator::Median => median_in_place(&mut v.clone()),
+            Aggregator::Min => v.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregator::Max => v.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregator::Range => {
+                let (min, max) = min_and_max(v.iter());
+                max - min
+            }
+            Aggregator::Sum => v.iter().sum(),
+            Aggregator::First => v[0],
+            Aggregator::Last => v[v.len() - 1],
+        }
+    }
+}
+
+/// How to treat the NANs of each time step when downsampling.
+/// Propagate gives NAN if any load of the step is NAN, as mean_or_nan;
+/// Ignore aggregates the finite loads, NAN if fewer than min_valid (see mean_ignore_nan).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NanPolicy {
+    Propagate,
+    Ignore { min_valid: usize },
+}
+
+impl NanPolicy {
+    /// Aggregate the loads of a step with the given aggregator, according to the policy.
+    pub fn aggregate(&self, agg: Aggregator, v: &Vec<f64>) -> f64 {
+        match self {
+            NanPolicy::Propagate => agg.aggregate(v),
+            NanPolicy::Ignore { min_valid } if agg == Aggregator::Mean => {
+                mean_ignore_nan(v, *min_valid)
+            }
+            NanPolicy::Ignore { min_valid } => {
+                let finite: Vec<f64> = v.iter().copied().filter(|l| !l.is_nan()).collect();
+                if finite.len() < *min_valid {
+                    f64::NAN
+                } else {
+                    agg.aggregate(&finite)
+                }
+            }
+        }
+    }
+
+    /// Weighted mean of the loads of a step, according to the policy for the NANs,
+    /// the weights of the NANs are not considered.
+    pub fn weighted_mean(&self, v: &[f64], w: &[f64]) -> f64 {
+        let (sum, sum_w, count) = v
+            .iter()
+            .zip(w)
+            .filter(|(l, _)| !l.is_nan())
+            .fold((0., 0., 0usize), |(s, sw, c), (l, w)| (s + l * w, sw + w, c + 1));
+        let min_valid = match self {
+            NanPolicy::Propagate => v.len(),
+            NanPolicy::Ignore { min_valid } => *min_valid,
+        };
+        if (count < min_valid.max(1)) | (sum_w <= 0.) {
+            f64::NAN
+        } else {
+            sum / sum_w
+        }
+    }
+}
+
+impl std::str::FromStr for Aggregator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Aggregator::Mean),
+            "median" => Ok(Aggregator::Median),
+            "min" => Ok(Aggregator::Min),
+            "max" => Ok(Aggregator::Max),
+            "range" => Ok(Aggregator::Range),
+            "sum" => Ok(Aggregator::Sum),
+            "first" => Ok(Aggregator::First),
+            "last" => Ok(Aggregator::Last),
+            _ => Err(format!("unknown aggregator: {}", s)),
+        }
+    }
+}
+
+/// Output format of the plotly plots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotFormat {
+    Html,
+    Svg,
+    Png,
+}
+
+impl PlotFormat {
+    /// Format from the extension of the path (case insensitive), None if not supported.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<PlotFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "html" => Some(PlotFormat::Html),
+            "svg" => Some(PlotFormat::Svg),
+            "png" => Some(PlotFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Raw reading of the logger with its datetime, see TimeLoad::from_csv_with_raw.
+pub type RawReading = (DateTime<FixedOffset>, String);
+
+// What parse_csv_lines does with the third column of the csv lines.
+enum ThirdColumn<'a> {
+    // not read
+    Ignore,
+    // quality flag, the load is set to NAN if it is one of these bad flags
+    Quality(&'a [&'a str]),
+    // raw reading, collected with its datetime
+    Raw(&'a mut Vec<RawReading>),
+}
+
+/// Provenance of each processed load, written as the quality column by load_process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quality {
+    /// Finite before the smoothing, i.e., a measured load.
+    Measured,
+    /// NAN before the smoothing and filled by it, e.g., by the moving average.
+    Filled,
+    /// Still NAN, e.g., a missing datetime or a load masked as bad.
+    Masked,
+    /// Set to NAN as part of an anomalous window, see find_anomalies.
+    Anomaly,
+    /// Replaced by its rolling median as an outlier, see hampel.
+    Replaced,
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quality::Measured => write!(f, "measured"),
+            Quality::Filled => write!(f, "filled"),
+            Quality::Masked => write!(f, "masked"),
+            Quality::Anomaly => write!(f, "anomaly"),
+            Quality::Replaced => write!(f, "replaced"),
+        }
+    }
+}
+
+/// Quality of each load from the loads before and after the smoothing,
+/// which fills the NANs, the indices of the outliers replaced before the smoothing,
+/// and the indices of the anomalies set to NAN.
+/// The anomalies stay flagged as such even when the smoothing fills them.
+pub fn quality_flags(
+    before: &[f64],
+    after: &[f64],
+    replaced: &[usize],
+    anomalies: &[usize],
+) -> Vec<Quality> {
+    assert_eq!(before.len(), after.len(), "loads before and after have different lengths");
+    let mut quality: Vec<Quality> = before
+        .iter()
+        .zip(after.iter())
+        .map(|(b, a)| match (b.is_nan(), a.is_nan()) {
+            (false, _) => Quality::Measured,
+            (true, false) => Quality::Filled,
+            (true, true) => Quality::Masked,
+        })
+        .collect();
+    for i in replaced.iter() {
+        if let Some(q) = quality.get_mut(*i) {
+            *q = Quality::Replaced;
+        }
+    }
+    for i in anomalies.iter() {
+        if let Some(q) = quality.get_mut(*i) {
+            *q = Quality::Anomaly;
+        }
+    }
+    quality
+}
+
+/// Dominant period of the loads, e.g., a diurnal cycle driven by the temperature.
+/// The loads are linearly interpolated on a regular grid with the smallest time step,
+/// see resample_to, and detrended; the period is that of the largest non-DC peak of their FFT,
+/// at most half of the time span.
+/// None if there are fewer than 4 datetimes, the datetimes are not ordered,
+/// or the loads are constant.
+#[cfg(feature = "fft")]
+pub fn dominant_period(time: &[DateTime<FixedOffset>], v: &[f64]) -> Option<chrono::Duration> {
+    use rustfft::{num_complex::Complex, FftPlanner};
+
+    if (time.len() != v.len()) || (time.len() < 4) {
+        return None;
+    }
+    let timeload = TimeLoad {
+        time: time.to_vec(),
+        load: v.to_vec(),
+    };
+    let step_ms = timeload.min_delta().num_milliseconds();
+    if step_ms <= 0 {
+        return None;
+    }
+    let span = time[time.len() - 1] - time[0];
+    let n = (span.num_milliseconds() / step_ms) as usize + 1;
+    let grid: Vec<DateTime<FixedOffset>> = (0..n)
+        .map(|i| time[0] + chrono::Duration::milliseconds(i as i64 * step_ms))
+        .collect();
+    let mut regular = timeload.resample_to(&grid, span);
+    // remove the linear trend, e.g., a drift, which would leak into the lowest frequencies
+    if regular.detrend_linear().0.is_nan() {
+        return None;
+    }
+    // the NANs at the ends are set to the trend
+    let mut buffer: Vec<Complex<f64>> = regular
+        .load
+        .iter()
+        .map(|l| Complex::new(if l.is_finite() { *l } else { 0. }, 0.))
+        .collect();
+    FftPlanner::new().plan_fft_forward(n).process(&mut buffer);
+    // at least two cycles in the time series, the longer periods are not resolved
+    let (k, power) = (2..=n / 2)
+        .map(|k| (k, buffer[k].norm_sqr()))
+        .fold((0, 0.), |m, c| if c.1 > m.1 { c } else { m });
+    // numerically constant after the detrending
+    if power < 1e-12 * n as f64 {
+        return None;
+    }
+    let period_ms = step_ms as f64 * n as f64 / k as f64;
+    Some(chrono::Duration::milliseconds(period_ms.round() as i64))
+}
+
+/// The kind of a detected event, see TimeLoad::events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    /// Window with an anomalous load IQR, see find_anomalies.
+    Anomaly,
+    /// Permanent shift of the baseline, see find_steps.
+    Step,
+    /// Run of identical loads, e.g., a stuck or clipped sensor.
+    Flatline,
+}
+
+impl EventKind {
+    // Priority of the kind when overlapping events are merged, see TimeLoad::events.
+    fn priority(&self) -> usize {
+        match self {
+            EventKind::Step => 2,
+            EventKind::Anomaly => 1,
+            EventKind::Flatline => 0,
+        }
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Anomaly => write!(f, "anomaly"),
+            EventKind::Step => write!(f, "step"),
+            EventKind::Flatline => write!(f, "flatline"),
+        }
+    }
+}
+
+/// A detected event, from its first to its last datetime (inclusive).
+/// The magnitude is the load range (max - min) over the event,
+/// for steps it is the absolute step magnitude (see step_magnitude),
+/// and for flatlines it is the repeated load value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub magnitude: f64,
+}
+
+/// Parameters of the detectors run by TimeLoad::events.
+/// As in load_process, each detector requires at least half of its window(s) to be valid.
+/// The step_width is the window before and after each step, see find_steps.
+/// A width (or min_len) of 0 disables the corresponding detector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventConfig {
+    pub anomaly_width: usize,
+    pub anomaly_iqr: f64,
+    pub step_width: usize,
+    pub min_step: f64,
+    pub flatline_min_len: usize,
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        EventConfig {
+            anomaly_width: 16,
+            anomaly_iqr: 40.,
+            step_width: 16,
+            min_step: 40.,
+            flatline_min_len: 12,
+        }
+    }
+}
+
 impl TimeLoad {
     /// Initiate a new TimeLoad instance
     /// using the given capacity for the time and load vectors
@@ -47,34 +466,462 @@ impl TimeLoad {
         timeload
     }
 
+    /// Initiate a TimeLoad from the time and load vectors,
+    /// return an error if they do not have the same length.
+    /// The ordering is not checked here, see is_ordered.
+    pub fn from_vecs(
+        time: Vec<DateTime<FixedOffset>>,
+        load: Vec<f64>,
+    ) -> Result<TimeLoad, LenMismatch> {
+        if time.len() != load.len() {
+            return Err(LenMismatch {
+                time_len: time.len(),
+                load_len: load.len(),
+            });
+        }
+        Ok(TimeLoad { time, load })
+    }
+
+    /// Initiate a TimeLoad from (datetime, load) pairs,
+    /// which keeps the time and load vectors with the same length.
+    pub fn from_pairs<I>(iter: I) -> TimeLoad
+    where
+        I: IntoIterator<Item = (DateTime<FixedOffset>, f64)>,
+    {
+        iter.into_iter().collect()
+    }
+
+    /// Push a datetime and its load, keeping the time and load vectors in lockstep.
+    pub fn push(&mut self, t: DateTime<FixedOffset>, l: f64) {
+        self.time.push(t);
+        self.load.push(l);
+    }
+
+    /// Number of datetimes in the time series.
+    pub fn len(&self) -> usize {
+        self.time.len()
+    }
+
+    /// True if the time series has no datetime.
+    pub fn is_empty(&self) -> bool {
+        self.time.is_empty()
+    }
+
+    /// Assert that the time and load vectors have the same length,
+    /// otherwise the zipped iterations silently truncate to the shorter one.
+    pub fn assert_consistent(&self) {
+        assert_eq!(
+            self.time.len(),
+            self.load.len(),
+            "time and load have different lengths"
+        );
+    }
+
+    /// Check that the time and load vectors have the same length,
+    /// return an error with the two lengths otherwise.
+    pub fn validate(&self) -> Result<(), LenMismatch> {
+        if self.time.len() != self.load.len() {
+            return Err(LenMismatch {
+                time_len: self.time.len(),
+                load_len: self.load.len(),
+            });
+        }
+        Ok(())
+    }
+
+    // Check the lengths in the debug builds, at the start of the methods that zip time and load.
+    fn debug_assert_consistent(&self) {
+        #[cfg(debug_assertions)]
+        self.assert_consistent();
+    }
+
     /// Initiate a TimeLoad from csv
-    /// setting load to NAN in case of load parsing errors,
-    /// but panic for datatime errors.
+    /// setting load to NAN in case of load parsing errors.
+    /// Return an error if the file cannot be opened or read,
+    /// or if a line has no datetime field.
     /// Do not check the continuity of the time series and presence of error flags,
     /// these are checked separately afterwards.
-    pub fn from_csv<P>(fin: P) -> TimeLoad
+    pub fn from_csv<P>(fin: P) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        TimeLoad::from_csv_with_columns(fin, 0usize, 1usize)
+    }
+
+    /// As from_csv, but also read the gzip-compressed files, detected from the .gz extension,
+    /// e.g., the archived loadcells.csv.gz, which requires the gzip feature.
+    pub fn from_csv_auto<P>(fin: P) -> Result<TimeLoad, Box<dyn Error>>
     where
         P: AsRef<Path>,
     {
-        let file = File::open(fin).unwrap();
+        TimeLoad::from_reader(TimeLoad::open_csv_auto(fin)?)
+    }
+
+    /// Read all the csv files in the directory whose name matches the pattern,
+    /// see matches_wildcard, e.g., one file per day, in parallel and as from_csv_auto.
+    /// The series are concatenated, sorted by datetime, and the duplicate datetimes are removed,
+    /// keeping the one of the file that comes first by name, e.g., the earlier day.
+    /// Return an error if no file matches or any of the files can not be read.
+    pub fn from_csv_dir<P>(dir: P, pattern: &str) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut paths: Vec<std::path::PathBuf> = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let matches = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| matches_wildcard(n, pattern));
+            if matches & path.is_file() {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Err(format!("no files matching {} in {:?}", pattern, dir.as_ref()).into());
+        }
+        paths.sort();
+        // the errors are not Send, return them as strings from the parallel reads
+        let timeloads: Vec<TimeLoad> = paths
+            .par_iter()
+            .map(|p| TimeLoad::from_csv_auto(p).map_err(|e| format!("{:?}: {}", p, e)))
+            .collect::<Result<Vec<TimeLoad>, String>>()?;
+        let mut timeload = TimeLoad::new(timeloads.iter().map(|tl| tl.len()).sum());
+        for tl in timeloads.into_iter() {
+            timeload.time.extend(tl.time);
+            timeload.load.extend(tl.load);
+        }
+        // stable sort, the duplicates stay in file order
+        timeload.sort_by_time();
+        timeload.dedup_timestamps(DupPolicy::KeepFirst);
+        Ok(timeload)
+    }
+
+    // Buffered reader of the csv file, decompressed if the extension is .gz.
+    fn open_csv_auto<P>(fin: P) -> Result<Box<dyn BufRead>, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let is_gz = fin
+            .as_ref()
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("gz"));
+        let file = File::open(&fin)?;
+        if !is_gz {
+            return Ok(Box::new(BufReader::new(file)));
+        }
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file))));
+        #[cfg(not(feature = "gzip"))]
+        return Err(format!("reading {:?} requires the gzip feature", fin.as_ref()).into());
+    }
+
+    /// As from_csv, but take the datetime and load from the given columns (zero-based),
+    /// e.g., for the exports of data historians with additional columns.
+    pub fn from_csv_with_columns<P>(
+        fin: P,
+        datetime_col: usize,
+        load_col: usize,
+    ) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(fin)?;
         let buf = BufReader::new(file);
-        let mut timeload = TimeLoad::new(10000 as usize);
+        TimeLoad::parse_csv_lines(
+            buf,
+            10000,
+            datetime_col,
+            load_col,
+            &DateTime::parse_from_rfc3339,
+            ThirdColumn::Ignore,
+            false,
+        )
+    }
 
-        for l in buf.lines().skip(1) {
-            let l_unwrap = match l {
-                Ok(l_ok) => l_ok,
-                Err(l_err) => {
-                    println!("Err, could not read/unwrap line {}", l_err);
-                    continue;
+    /// As from_csv, but find the datetime and load columns by their name in the header line.
+    /// Return an error if any of the two names is not found.
+    pub fn from_csv_by_header<P>(
+        fin: P,
+        datetime_name: &str,
+        load_name: &str,
+    ) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(fin)?;
+        let mut buf = BufReader::new(file);
+        let mut header = String::new();
+        buf.read_line(&mut header)?;
+        let find_col = |name: &str| {
+            header
+                .trim_end()
+                .split(',')
+                .position(|h| h.trim() == name)
+                .ok_or(MissingColumn {
+                    column: String::from(name),
+                    line: 1usize,
+                })
+        };
+        let datetime_col = find_col(datetime_name)?;
+        let load_col = find_col(load_name)?;
+        // put the header back in front, it is skipped by the parsing loop
+        let buf = header.as_bytes().chain(buf);
+        TimeLoad::parse_csv_lines(
+            buf,
+            10000,
+            datetime_col,
+            load_col,
+            &DateTime::parse_from_rfc3339,
+            ThirdColumn::Ignore,
+            false,
+        )
+    }
+
+    /// Initiate a TimeLoad from any buffered reader with the csv content,
+    /// header included, as done by from_csv.
+    pub fn from_reader<R: BufRead>(buf: R) -> Result<TimeLoad, Box<dyn Error>> {
+        TimeLoad::from_reader_with_capacity(buf, 10000)
+    }
+
+    /// As from_reader, but allocate the time and load vectors for the given number of rows,
+    /// to avoid reallocations when the length is known in advance.
+    pub fn from_reader_with_capacity<R: BufRead>(
+        buf: R,
+        capacity: usize,
+    ) -> Result<TimeLoad, Box<dyn Error>> {
+        TimeLoad::parse_csv_lines(
+            buf,
+            capacity,
+            0usize,
+            1usize,
+            &DateTime::parse_from_rfc3339,
+            ThirdColumn::Ignore,
+            false,
+        )
+    }
+
+    /// Check that the csv file looks like a load time series before reading it,
+    /// e.g., to fail fast with a clear message when the wrong file is given.
+    /// The gzip-compressed files are read as in from_csv_auto.
+    /// The header should start with the datetime and load columns,
+    /// and the first CSV_SAMPLE_ROWS data rows should have a datetime, in the format
+    /// detected from the first row, and a load, possibly empty or an error code string
+    /// (see ErrorCodes::is_code_str). The rest of the file is not read, blank lines are skipped.
+    pub fn validate_csv<P>(fin: P) -> Result<CsvInfo, CsvError>
+    where
+        P: AsRef<Path>,
+    {
+        let csv_error = |line: Option<usize>, message: String| CsvError { line, message };
+        let buf = TimeLoad::open_csv_auto(&fin)
+            .map_err(|e| csv_error(None, format!("could not open the file, {}", e)))?;
+        let mut lines = buf.lines().enumerate();
+        let header = match lines.next() {
+            Some((_, Ok(l))) => l,
+            Some((_, Err(e))) => return Err(csv_error(Some(1), format!("not text, {}", e))),
+            None => return Err(csv_error(None, String::from("empty file"))),
+        };
+        let header: Vec<String> = header.split(',').map(|h| h.trim().to_string()).collect();
+        if (header.len() < 2) | !header[0].to_lowercase().contains("datetime") {
+            return Err(csv_error(
+                Some(1),
+                format!("expected a header starting with datetime,load, got {:?}", header),
+            ));
+        }
+
+        let mut info = CsvInfo {
+            columns: header.len(),
+            header,
+            sampled_rows: 0,
+            datetime_format: None,
+        };
+        for (n, l) in lines {
+            let l = l.map_err(|e| csv_error(Some(n + 1), format!("not text, {}", e)))?;
+            if l.trim().is_empty() {
+                continue;
+            }
+            if info.sampled_rows == CSV_SAMPLE_ROWS {
+                break;
+            }
+            info.sampled_rows += 1;
+            let fields: Vec<&str> = l.split(',').map(|f| f.trim()).collect();
+            if fields.len() < 2 {
+                return Err(csv_error(
+                    Some(n + 1),
+                    format!("expected at least 2 columns, got {}", fields.len()),
+                ));
+            }
+            if info.sampled_rows == 1 {
+                info.datetime_format = match DateTime::parse_from_rfc3339(fields[0]) {
+                    Ok(_) => None,
+                    Err(_) => match CSV_DATETIME_FORMATS
+                        .iter()
+                        .find(|f| NaiveDateTime::parse_from_str(fields[0], f).is_ok())
+                    {
+                        Some(f) => Some(*f),
+                        None => {
+                            return Err(csv_error(
+                                Some(n + 1),
+                                format!("unknown datetime format {}", fields[0]),
+                            ))
+                        }
+                    },
+                };
+            }
+            let datetime_ok = match info.datetime_format {
+                None => DateTime::parse_from_rfc3339(fields[0]).is_ok(),
+                Some(f) => NaiveDateTime::parse_from_str(fields[0], f).is_ok(),
+            };
+            if !datetime_ok {
+                return Err(csv_error(Some(n + 1), format!("invalid datetime {}", fields[0])));
+            }
+            let load_ok = fields[1].is_empty()
+                || fields[1].parse::<f64>().is_ok()
+                || ErrorCodes::is_code_str(fields[1]);
+            if !load_ok {
+                return Err(csv_error(Some(n + 1), format!("invalid load {}", fields[1])));
+            }
+        }
+        if info.sampled_rows == 0 {
+            return Err(csv_error(None, String::from("no data rows after the header")));
+        }
+        Ok(info)
+    }
+
+    /// As from_csv, but read the quality flag of the third column, as written by some loggers,
+    /// and set the load to NAN when the flag is one of the bad flags, e.g., ["SUSPECT", "BAD"].
+    /// Lines without the quality column are considered good.
+    pub fn from_csv_with_quality<P>(fin: P, bad_flags: &[&str]) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(fin)?;
+        let buf = BufReader::new(file);
+        TimeLoad::parse_csv_lines(
+            buf,
+            10000,
+            0usize,
+            1usize,
+            &DateTime::parse_from_rfc3339,
+            ThirdColumn::Quality(bad_flags),
+            false,
+        )
+    }
+
+    /// As from_csv_auto, but also return the raw readings of the third column with their datetime,
+    /// as written by load_log, from the same parse; the lines without it have no raw reading.
+    pub fn from_csv_with_raw<P>(fin: P) -> Result<(TimeLoad, Vec<RawReading>), Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let mut raw_readings: Vec<RawReading> = Vec::new();
+        let timeload = TimeLoad::parse_csv_lines(
+            TimeLoad::open_csv_auto(fin)?,
+            10000,
+            0usize,
+            1usize,
+            &DateTime::parse_from_rfc3339,
+            ThirdColumn::Raw(&mut raw_readings),
+            false,
+        )?;
+        Ok((timeload, raw_readings))
+    }
+
+    /// As from_csv, but parse the datetime with the given format instead of RFC 3339,
+    /// e.g., "%Y-%m-%d %H:%M:%S" or "%m/%d/%Y %H:%M" for legacy exports.
+    /// If the format has no offset (%z or %:z), the datetime is taken in the assumed offset.
+    pub fn from_csv_with_format<P>(
+        fin: P,
+        fmt: &str,
+        assumed_offset: FixedOffset,
+    ) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(fin)?;
+        let buf = BufReader::new(file);
+        let with_offset = fmt.contains("%z") || fmt.contains("%:z");
+        let parse_datetime = |s: &str| {
+            if with_offset {
+                DateTime::parse_from_str(s, fmt)
+            } else {
+                NaiveDateTime::parse_from_str(s, fmt)
+                    .map(|n| assumed_offset.from_local_datetime(&n).unwrap())
+            }
+        };
+        TimeLoad::parse_csv_lines(
+            buf,
+            10000,
+            0usize,
+            1usize,
+            &parse_datetime,
+            ThirdColumn::Ignore,
+            false,
+        )
+    }
+
+    /// Initiate a TimeLoad from a csv file that is still being written, e.g., by load_log.
+    /// As from_csv, but drop the last line if it is incomplete (no comma or empty load),
+    /// instead of pushing a spurious NAN for a reading that has not been written yet.
+    pub fn from_csv_live<P>(fin: P) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(fin)?;
+        let buf = BufReader::new(file);
+        TimeLoad::parse_csv_lines(
+            buf,
+            10000,
+            0usize,
+            1usize,
+            &DateTime::parse_from_rfc3339,
+            ThirdColumn::Ignore,
+            true,
+        )
+    }
+
+    /// Parsing loop shared by the csv readers, skip the header line.
+    fn parse_csv_lines<R: BufRead>(
+        buf: R,
+        capacity: usize,
+        datetime_col: usize,
+        load_col: usize,
+        parse_datetime: &dyn Fn(&str) -> chrono::ParseResult<DateTime<FixedOffset>>,
+        mut third: ThirdColumn,
+        drop_incomplete_last: bool,
+    ) -> Result<TimeLoad, Box<dyn Error>> {
+        let mut timeload = TimeLoad::new(capacity);
+
+        // enumerate before skipping the header, so that n + 1 is the line number in the file
+        let mut lines = buf.lines().enumerate().skip(1).peekable();
+        while let Some((n, l)) = lines.next() {
+            let l_unwrap = l?;
+            if l_unwrap.trim().is_empty() {
+                continue;
+            }
+            let l_split: Vec<&str> = l_unwrap.split(',').collect();
+            let l_split_datetime = match l_split.get(datetime_col).copied() {
+                Some(datetime) if !datetime.trim().is_empty() => datetime,
+                _ => {
+                    return Err(Box::new(MissingColumn {
+                        column: String::from("datetime"),
+                        line: n + 1,
+                    }))
+                }
+            };
+            let l_split_load = match l_split.get(load_col).copied() {
+                Some(load) if !load.trim().is_empty() => load,
+                _ if drop_incomplete_last && lines.peek().is_none() => {
+                    eprintln!("Dropping incomplete last line: {}", l_unwrap);
+                    break;
                 }
+                Some(load) => load,
+                None => "",
             };
-            let mut l_split = l_unwrap.split(',');
-            let l_split_datetime = l_split.next().unwrap();
-            let l_split_load = l_split.next().unwrap();
-            let parsed_datetime = match DateTime::parse_from_rfc3339(l_split_datetime) {
+            let parsed_datetime = match parse_datetime(l_split_datetime) {
                 Ok(parsed_datetime) => parsed_datetime,
                 Err(e) => {
-                    println!(
+                    eprintln!(
                         "Could not parse datetime: {}, error {}",
                         l_split_datetime, e
                     );
@@ -82,10 +929,27 @@ impl TimeLoad {
                 }
             };
             timeload.time.push(parsed_datetime);
+            match &mut third {
+                ThirdColumn::Ignore => {}
+                ThirdColumn::Quality(bad_flags) => {
+                    if let Some(q) = l_split.get(2) {
+                        if bad_flags.contains(&q.trim()) {
+                            timeload.load.push(f64::NAN);
+                            continue;
+                        }
+                    }
+                }
+                ThirdColumn::Raw(raw_readings) => {
+                    // the raw reading is the rest of the line, as written by load_log
+                    if let Some(raw) = l_unwrap.splitn(3, ',').nth(2) {
+                        raw_readings.push((parsed_datetime, raw.to_owned()));
+                    }
+                }
+            }
             match l_split_load.parse::<f64>() {
                 Ok(parsed_load) => timeload.load.push(parsed_load),
                 Err(e) => {
-                    println!(
+                    eprintln!(
                         "Could not parse load: {}, at datetime {}. Error: {}",
                         l_split_load, parsed_datetime, e
                     );
@@ -93,47 +957,217 @@ impl TimeLoad {
                 }
             }
         }
-        timeload
+        Ok(timeload)
+    }
+
+    /// Convert all the datetimes to the given fixed offset, e.g., the standard time of the site,
+    /// so that the time series has no daylight saving jumps.
+    pub fn to_timezone(&mut self, offset: FixedOffset) {
+        self.time
+            .iter_mut()
+            .for_each(|t| *t = t.with_timezone(&offset));
+    }
+
+    /// Convert all the datetimes to the current offset of the machine's local timezone.
+    pub fn to_local_timezone(&mut self) {
+        self.to_timezone(Local::now().offset().fix());
+    }
+
+    // Assert that all the datetimes have the same offset,
+    // mixed offsets (e.g., after a DST transition) break the continuity check.
+    pub fn assert_fixed_offset(&self) {
+        if let Some(first) = self.time.first() {
+            let offset = first.offset();
+            self.time.iter().for_each(|t| {
+                assert!(
+                    t.offset() == offset,
+                    "time series has mixed offsets: {} and {}",
+                    first,
+                    t
+                )
+            });
+        }
     }
 
     // Assert that the time series is ordered.
     pub fn is_ordered(&self) {
-        self.time.windows(2).for_each(|w| {
-            assert!(
-                w[1] > w[0],
-                "time series is not ordered: {} < {}",
-                w[1],
-                w[0]
-            )
-        });
+        if let Err(e) = self.try_is_ordered() {
+            panic!("{}", e);
+        }
     }
 
     // Assert that the time series is ordered and continuous.
     pub fn is_ordered_and_continuous(&self) {
+        if let Err(e) = self.try_is_ordered() {
+            panic!("{}", e);
+        }
+        if let Err(e) = self.try_is_continuous() {
+            panic!("{}", e);
+        }
+    }
+
+    /// Remove the datetimes and loads at the given indices, keeping the two vectors in lockstep,
+    /// e.g., to drop the anomalies instead of setting them to NAN (see discharge_by_index).
+    pub fn discharge_indices(&mut self, indices: &[usize]) {
+        self.debug_assert_consistent();
+        self.time = discharge_by_index(&self.time, indices);
+        self.load = discharge_by_index(&self.load, indices);
+    }
+
+    /// Sort the time series by datetime, moving each load with its datetime,
+    /// e.g., after concatenating overlapping files. The sort is stable.
+    pub fn sort_by_time(&mut self) {
+        self.debug_assert_consistent();
+        let mut permutation: Vec<usize> = (0..self.time.len()).collect();
+        permutation.sort_by_key(|i| self.time[*i]);
+        self.time = permutation.iter().map(|i| self.time[*i]).collect();
+        self.load = permutation.iter().map(|i| self.load[*i]).collect();
+    }
+
+    /// Collapse the consecutive equal datetimes into one, with the load given by the policy,
+    /// e.g., two readings in the same minute. Sort the time series first, see sort_by_time.
+    /// Return the number of removed duplicates.
+    pub fn dedup_timestamps(&mut self, policy: DupPolicy) -> usize {
+        self.debug_assert_consistent();
+        let len = self.time.len();
+        let mut timeload = TimeLoad::new(len);
+        let mut i = 0usize;
+        while i < len {
+            let mut j = i + 1;
+            while (j < len) && (self.time[j] == self.time[i]) {
+                j += 1;
+            }
+            let load = match policy {
+                DupPolicy::KeepFirst => self.load[i],
+                DupPolicy::KeepLast => self.load[j - 1],
+                DupPolicy::Average => mean_or_nan(&self.load[i..j].to_vec()),
+            };
+            timeload.push(self.time[i], load);
+            i = j;
+        }
+        let removed = len - timeload.len();
+        *self = timeload;
+        removed
+    }
+
+    /// Check that each datetime is after the previous one,
+    /// otherwise return the first offending index and the two datetimes.
+    pub fn try_is_ordered(&self) -> Result<(), OrderError> {
+        match self.time.windows(2).position(|w| w[1] <= w[0]) {
+            Some(i) => Err(OrderError {
+                index: i + 1,
+                previous: self.time[i],
+                next: self.time[i + 1],
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that all the time steps are equal to the first one,
+    /// otherwise return the first offending index and the two datetimes.
+    pub fn try_is_continuous(&self) -> Result<(), ContinuityError> {
+        if self.time.len() < 3 {
+            return Ok(());
+        }
+        let expected_step = self.time[1] - self.time[0];
+        match self.time.windows(2).position(|w| w[1] - w[0] != expected_step) {
+            Some(i) => Err(ContinuityError {
+                index: i + 1,
+                previous: self.time[i],
+                next: self.time[i + 1],
+                expected_step,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // The smallest time step of the time series, taken as its sampling interval.
+    fn min_delta(&self) -> chrono::Duration {
         self.time
             .windows(2)
-            .map(|w| {
-                assert!( w[1] > w[0], "time series is not ordered: {} < {}", w[1], w[0]);
-                w[1] - w[0]
-            })
-            .reduce(|wp, wn| {
-                assert_eq!(wp, wn, "time series is not continuous");
-                wn
-            });
+            .map(|dtw| dtw[1] - dtw[0])
+            .min()
+            .unwrap()
     }
 
-    /// Fill the datetime gaps with NANs to have continuous datetime.
-    /// Take a reference to the read TimeLoad and return a new continuous TimeLoad.
-    /// Heuristically use the minimum time interval in the data to determine the desired time step for the output.
-    pub fn fill_missing_with_nan(&self) -> TimeLoad {
-        let min_delta = self
-            .time
+    // The smallest time step between different datetimes, None if there are none.
+    fn min_positive_delta(&self) -> Option<chrono::Duration> {
+        self.time
             .windows(2)
             .map(|dtw| dtw[1] - dtw[0])
+            .filter(|d| *d > chrono::Duration::zero())
             .min()
-            .unwrap();
-        let mut timeload = TimeLoad::new(self.time.len());
-        for (dtw, load) in self.time.windows(2).zip(self.load.iter()) {
+    }
+
+    /// Find the datetime gaps without filling them, e.g., to report the data completeness.
+    /// Each gap is given as the datetimes before and after it and the number of missing samples.
+    /// If the expected step is not given, the smallest positive time step is used,
+    /// as in fill_missing_with_nan but skipping the duplicate datetimes;
+    /// no gaps are found if all the datetimes are the same.
+    pub fn find_gaps(
+        &self,
+        expected_step: Option<chrono::Duration>,
+    ) -> Vec<(DateTime<FixedOffset>, DateTime<FixedOffset>, usize)> {
+        let step = match expected_step.or_else(|| self.min_positive_delta()) {
+            Some(step) => step,
+            None => return Vec::new(),
+        };
+        let step_ms = step.num_milliseconds();
+        assert!(step_ms > 0, "the expected step must be positive");
+        self.time
+            .windows(2)
+            .filter(|dtw| dtw[1] - dtw[0] > step)
+            .map(|dtw| {
+                let delta_ms = (dtw[1] - dtw[0]).num_milliseconds();
+                let missing = ((delta_ms - 1) / step_ms) as usize;
+                (dtw[0], dtw[1], missing)
+            })
+            .collect()
+    }
+
+    /// The subset with the datetimes from start (included) to end (excluded).
+    /// The time series is expected to be ordered, the bounds are found with a binary search.
+    pub fn slice_time(&self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> TimeLoad {
+        self.debug_assert_consistent();
+        let s = self.time.partition_point(|t| *t < start);
+        let e = std::cmp::max(self.time.partition_point(|t| *t < end), s);
+        TimeLoad {
+            time: self.time[s..e].to_vec(),
+            load: self.load[s..e].to_vec(),
+        }
+    }
+
+    /// Split the time series into contiguous segments wherever two consecutive datetimes
+    /// are more than max_gap apart, e.g., to process each deployment separately.
+    /// The time series is expected to be ordered, each segment is then ordered too.
+    pub fn split_at_gaps(&self, max_gap: chrono::Duration) -> Vec<TimeLoad> {
+        self.debug_assert_consistent();
+        let mut segments: Vec<TimeLoad> = Vec::new();
+        let mut start = 0usize;
+        for i in 1..=self.time.len() {
+            if (i < self.time.len()) && (self.time[i] - self.time[i - 1] <= max_gap) {
+                continue;
+            }
+            segments.push(TimeLoad {
+                time: self.time[start..i].to_vec(),
+                load: self.load[start..i].to_vec(),
+            });
+            start = i;
+        }
+        segments
+    }
+
+    /// Fill the datetime gaps with NANs to have continuous datetime.
+    /// Take a reference to the read TimeLoad and return a new continuous TimeLoad.
+    /// Heuristically use the minimum time interval in the data to determine the desired time step for the output.
+    /// Note that a single off-cadence sample (e.g., a double reading) makes the step much
+    /// smaller than the logging interval and the output much longer;
+    /// use fill_missing_with_nan_step when the logging interval is known.
+    pub fn fill_missing_with_nan(&self) -> TimeLoad {
+        self.debug_assert_consistent();
+        let min_delta = self.min_delta();
+        let mut timeload = TimeLoad::new(self.time.len());
+        for (dtw, load) in self.time.windows(2).zip(self.load.iter()) {
             let mut current_dt: DateTime<FixedOffset> = dtw[0];
             timeload.time.push(current_dt);
             timeload.load.push(*load);
@@ -148,107 +1182,651 @@ impl TimeLoad {
         timeload
     }
 
+    /// As fill_missing_with_nan, but with the given time step, i.e., the known logging interval.
+    /// The output grid is aligned to multiples of the step from the midnight of the first datetime,
+    /// in its offset, and goes from the grid point of the first datetime to that of the last one.
+    /// Each sample is snapped to the nearest grid point, thus a sample off the cadence
+    /// (e.g., logged a few seconds late) is kept; when more samples snap to the same grid point
+    /// (e.g., double readings), the closest one is kept and the others are dropped.
+    pub fn fill_missing_with_nan_step(&self, step: chrono::Duration) -> TimeLoad {
+        self.debug_assert_consistent();
+        let step_ms = step.num_milliseconds();
+        assert!(step_ms > 0, "the time step must be positive");
+        let first = self.time[0];
+        let midnight = first
+            .offset()
+            .from_local_datetime(&first.date_naive().and_time(NaiveTime::MIN))
+            .unwrap();
+        // index of the nearest grid point and the distance from it, in milliseconds
+        let snap = |t: &DateTime<FixedOffset>| {
+            let ms = (*t - midnight).num_milliseconds();
+            let k = (ms + step_ms / 2).div_euclid(step_ms);
+            (k, (ms - k * step_ms).abs())
+        };
+        let snapped: Vec<(i64, i64)> = self.time.iter().map(snap).collect();
+        let k_first = snapped.iter().map(|(k, _)| *k).min().unwrap();
+        let k_last = snapped.iter().map(|(k, _)| *k).max().unwrap();
+        let len = (k_last - k_first + 1) as usize;
+        let mut load = vec![f64::NAN; len];
+        let mut distance: Vec<Option<i64>> = vec![None; len];
+        let mut dropped = 0usize;
+        for ((k, d), l) in snapped.iter().zip(self.load.iter()) {
+            let slot = (k - k_first) as usize;
+            match distance[slot] {
+                Some(best) => {
+                    dropped += 1;
+                    if *d < best {
+                        distance[slot] = Some(*d);
+                        load[slot] = *l;
+                    }
+                }
+                None => {
+                    distance[slot] = Some(*d);
+                    load[slot] = *l;
+                }
+            }
+        }
+        if dropped > 0 {
+            eprintln!("warning, dropped {} samples sharing a {} time step", dropped, step);
+        }
+        let time: Vec<DateTime<FixedOffset>> = (k_first..=k_last)
+            .map(|k| midnight + chrono::Duration::milliseconds(k * step_ms))
+            .collect();
+        TimeLoad { time, load }
+    }
+
+    /// Merge two time series, e.g., the data collected by two loggers.
+    /// The result is ordered by datetime and has no duplicate datetimes:
+    /// for the duplicates, the finite load is preferred over NAN,
+    /// and self is preferred over other when both are finite.
+    pub fn merge(&self, other: &TimeLoad) -> TimeLoad {
+        self.debug_assert_consistent();
+        other.debug_assert_consistent();
+        let mut pairs: Vec<(DateTime<FixedOffset>, f64)> = self
+            .time
+            .iter()
+            .cloned()
+            .zip(self.load.iter().cloned())
+            .chain(other.time.iter().cloned().zip(other.load.iter().cloned()))
+            .collect();
+        // stable sort, self stays before other for the same datetime
+        pairs.sort_by_key(|p| p.0);
+        let mut timeload = TimeLoad::new(pairs.len());
+        for (t, l) in pairs.into_iter() {
+            match timeload.time.last() {
+                Some(last) if *last == t => {
+                    let last_load = timeload.load.last_mut().unwrap();
+                    if last_load.is_nan() {
+                        *last_load = l;
+                    }
+                }
+                _ => {
+                    timeload.time.push(t);
+                    timeload.load.push(l);
+                }
+            }
+        }
+        timeload
+    }
+
     /// Set to NAN the load values corresponsiding to the input bad datetimes.
     pub fn replace_bad_datetimes_with_nan(&mut self, bad_datetimes: Vec<DateTime<FixedOffset>>) {
         for bdt in bad_datetimes.into_iter() {
             match self.time.iter().position(|d| *d == bdt) {
                 Some(i) => self.load[i] = f64::NAN,
-                None => println!("could not find and exclude bad datetime {}", bdt),
+                None => eprintln!("could not find and exclude bad datetime {}", bdt),
+            }
+        }
+    }
+
+    /// Set to NAN all the loads measured within the interval, start and end included,
+    /// e.g., for a known bad period instead of listing all its bad datetimes.
+    pub fn replace_bad_interval_with_nan(
+        &mut self,
+        start: DateTime<FixedOffset>,
+        end: DateTime<FixedOffset>,
+    ) {
+        self.debug_assert_consistent();
+        self.time
+            .iter()
+            .zip(self.load.iter_mut())
+            .filter(|(t, _)| (**t >= start) & (**t <= end))
+            .for_each(|(_, l)| *l = f64::NAN);
+    }
+
+    /// Re-zero the load cell, subtract the mean of the finite loads in the time window,
+    /// start and end included, from all the loads and return this offset.
+    /// Error if the window has no finite loads, the loads are then left unchanged.
+    pub fn tare(
+        &mut self,
+        window_start: DateTime<FixedOffset>,
+        window_end: DateTime<FixedOffset>,
+    ) -> Result<f64, NoValidLoads> {
+        self.debug_assert_consistent();
+        let window_loads: Vec<f64> = self
+            .time
+            .iter()
+            .zip(self.load.iter())
+            .filter(|(t, l)| (**t >= window_start) & (**t <= window_end) & l.is_finite())
+            .map(|(_, l)| *l)
+            .collect();
+        if window_loads.is_empty() {
+            return Err(NoValidLoads {
+                start: window_start,
+                end: window_end,
+            });
+        }
+        let offset = window_loads.iter().sum::<f64>() / window_loads.len() as f64;
+        self.load.iter_mut().for_each(|l| *l -= offset);
+        Ok(offset)
+    }
+
+    /// Set to NAN the load values where the mask is false,
+    /// the mask must have one element per load value.
+    pub fn apply_mask(&mut self, mask: &[bool]) {
+        assert_eq!(
+            mask.len(),
+            self.load.len(),
+            "mask and load have different lengths"
+        );
+        self.load
+            .iter_mut()
+            .zip(mask.iter())
+            .for_each(|(l, m)| {
+                if !m {
+                    *l = f64::NAN;
+                }
+            });
+    }
+
+    /// Find the longest run of consecutive finite loads,
+    /// return the indices of its first and last element (inclusive),
+    /// or None if there are no finite loads.
+    /// In case of runs with the same length, the first one is returned.
+    pub fn longest_valid_run(&self) -> Option<(usize, usize)> {
+        let mut longest: Option<(usize, usize)> = None;
+        let mut run_start: Option<usize> = None;
+        for (i, l) in self.load.iter().enumerate() {
+            if l.is_finite() {
+                let s = *run_start.get_or_insert(i);
+                match longest {
+                    Some((ls, le)) if le - ls >= i - s => {}
+                    _ => longest = Some((s, i)),
+                }
+            } else {
+                run_start = None;
+            }
+        }
+        longest
+    }
+
+    /// Reduce the series to at most n points, e.g., for thumbnails.
+    /// Split the series in n / 2 buckets with the same number of samples and
+    /// keep the minimum and maximum of each bucket, in their time order,
+    /// so that the spikes are preserved.
+    /// A bucket without finite loads gives a single NAN, to preserve the gaps.
+    /// With n equal to 0 the series is empty.
+    pub fn downsample_to(&self, n: usize) -> TimeLoad {
+        if self.load.len() <= n {
+            return self.clone();
+        }
+        if n == 0 {
+            return TimeLoad::new(0);
+        }
+        let n_buckets = std::cmp::max(n / 2, 1usize);
+        let bucket_len = self.load.len().div_ceil(n_buckets);
+        let mut timeload = TimeLoad::new(2 * n_buckets);
+        for (tb, lb) in self.time.chunks(bucket_len).zip(self.load.chunks(bucket_len)) {
+            let finite = lb.iter().enumerate().filter(|(_, l)| l.is_finite());
+            let (imin, imax) = match finite.clone().next() {
+                Some((i, _)) => finite.fold((i, i), |(imin, imax), (i, l)| {
+                    let imin = if *l < lb[imin] { i } else { imin };
+                    let imax = if *l > lb[imax] { i } else { imax };
+                    (imin, imax)
+                }),
+                None => {
+                    timeload.time.push(tb[0]);
+                    timeload.load.push(f64::NAN);
+                    continue;
+                }
+            };
+            let (first, second) = if imin <= imax { (imin, imax) } else { (imax, imin) };
+            timeload.time.push(tb[first]);
+            timeload.load.push(lb[first]);
+            if (second != first) & (n > 1) {
+                timeload.time.push(tb[second]);
+                timeload.load.push(lb[second]);
             }
         }
+        timeload
+    }
+
+    /// Downsample to hourly data, with the NANs treated according to the policy
+    pub fn to_hourly(& self, nan: NanPolicy) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.to_interval(chrono::Duration::hours(1i64), nan)
+    }
+
+    /// Downsample to the given time step with the mean of the loads of each step,
+    /// with the NANs treated according to the policy.
+    /// Each datetime is rounded to the nearest multiple of the step from its local midnight,
+    /// e.g., 15 minutes or 6 hours, thus the steps should divide the day evenly.
+    /// The time series is expected to be ordered.
+    pub fn to_interval(
+        & self,
+        step: chrono::Duration,
+        nan: NanPolicy,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.downsample_with_nan_policy(step, Aggregator::Mean, nan)
+    }
+
+    /// Downsample to the given time step as to_interval, with the given aggregation
+    /// of the finite loads of each step (NAN if none).
+    pub fn downsample(
+        & self,
+        step: chrono::Duration,
+        agg: Aggregator,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.downsample_with_nan_policy(step, agg, NanPolicy::Ignore { min_valid: 1 })
+    }
+
+    /// Downsample as downsample, with the NANs of each step treated according to the policy.
+    pub fn downsample_with_nan_policy(
+        & self,
+        step: chrono::Duration,
+        agg: Aggregator,
+        nan: NanPolicy,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.downsample_by(step, None, &|v, _| nan.aggregate(agg, v))
+    }
+
+    /// Downsample as to_interval, with the mean of each step weighted by the time
+    /// represented by each load, i.e., half the gap to each neighbor (see time_weights).
+    /// Unlike the plain mean, it is not biased toward the densely sampled parts of the step,
+    /// e.g., when the logging interval changed, for which the nominal step should be
+    /// the longest logging interval; without it, the smallest time step is used.
+    pub fn to_interval_time_weighted(
+        & self,
+        step: chrono::Duration,
+        nominal_step: Option<chrono::Duration>,
+        nan: NanPolicy,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        let nominal_step = nominal_step.or_else(|| self.min_positive_delta());
+        self.downsample_by(step, nominal_step, &|v, w| nan.weighted_mean(v, w))
+    }
+
+    /// The time represented by each load in seconds, half the gap to each neighbor,
+    /// each half capped at half the nominal step, i.e., the logging interval,
+    /// so that the loads next to a data gap do not stand for the missing ones.
+    /// The first and last loads have only one neighbor, thus half of its gap,
+    /// while a single load without neighbors represents the whole nominal step.
+    pub fn time_weights(&self, nominal_step: chrono::Duration) -> Vec<f64> {
+        let n = self.time.len();
+        let max_half_gap = nominal_step.num_milliseconds() as f64 / 2000.;
+        if n == 1 {
+            return vec![2. * max_half_gap];
+        }
+        let half_gap = |i: usize| {
+            let gap = (self.time[i + 1] - self.time[i]).num_milliseconds() as f64 / 2000.;
+            gap.min(max_half_gap)
+        };
+        (0..n)
+            .map(|i| {
+                let before = if i > 0 { half_gap(i - 1) } else { 0. };
+                let after = if i + 1 < n { half_gap(i) } else { 0. };
+                before + after
+            })
+            .collect()
     }
 
-    /// Downsample to hourly data
-    pub fn to_hourly(& self) -> Result<TimeLoad, EmptyTimeLoad> {
+    /// Group the loads by time step and aggregate the loads of each step
+    /// with their time weights for the nominal step, or equal weights without it,
+    /// see downsample_with_nan_policy and to_interval_time_weighted.
+    fn downsample_by(
+        & self,
+        step: chrono::Duration,
+        nominal_step: Option<chrono::Duration>,
+        aggregate: &dyn Fn(&Vec<f64>, &Vec<f64>) -> f64,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.debug_assert_consistent();
 
         if self.time.len() == 0 {
             return Err(EmptyTimeLoad{})
         }
+        let step_seconds = step.num_seconds();
+        assert!(step_seconds > 0, "the time step should be at least one second");
 
         // heuristic estimation of the final length for allocation
-        let mut hourly_timeload = TimeLoad::new(self.time.len() / 60);
-        let mut hourly_time: Option<DateTime<FixedOffset>> = None;
-        let mut hourly_loads: Vec<f64> = Vec::with_capacity(60);
+        let samples_per_step = std::cmp::max(step_seconds / 60, 1i64) as usize;
+        let mut interval_timeload = TimeLoad::new(self.time.len() / samples_per_step + 1);
+        let mut interval_time: Option<DateTime<FixedOffset>> = None;
+        let mut interval_loads: Vec<f64> = Vec::with_capacity(samples_per_step);
+        let mut interval_weights: Vec<f64> = Vec::with_capacity(samples_per_step);
+        let weights = match nominal_step {
+            Some(nominal_step) => self.time_weights(nominal_step),
+            None => vec![1.; self.time.len()],
+        };
 
         self.time
             .iter()
             .zip(self.load.iter())
-            .for_each(|(t, l)| {
+            .zip(weights.iter())
+            .for_each(|((t, l), w)| {
 
-                // get the hourly datetime
-                let mut iter_time = t.clone();
-                if iter_time.minute() >= 30u32 {
-                    iter_time += chrono::Duration::hours(1i64);
-                }
-                iter_time = iter_time.with_minute(0u32).unwrap();
-                iter_time = iter_time.trunc_subsecs(0u16);
+                // get the datetime of the step, rounding half up
+                let since_midnight = t.num_seconds_from_midnight() as i64;
+                let rounded = (since_midnight + step_seconds / 2) / step_seconds * step_seconds;
+                let iter_time = t.trunc_subsecs(0u16)
+                    + chrono::Duration::seconds(rounded - since_midnight);
 
-                match hourly_time {
+                match interval_time {
 
-                    Some(ht) => {
+                    Some(it) => {
 
-                        if ht == iter_time {
-                            if !l.is_nan() {
-                                hourly_loads.push(*l)
-                            }
+                        if it == iter_time {
+                            interval_loads.push(*l);
+                            interval_weights.push(*w);
 
                         } else {
 
-                            // finish and push the previous hourly time and mean load
-                            let hourly_mean_load = mean_or_nan(&hourly_loads);
-                            hourly_timeload.time.push(ht);
-                            hourly_timeload.load.push(hourly_mean_load);
+                            // finish and push the previous time and aggregated load
+                            interval_timeload.time.push(it);
+                            interval_timeload
+                                .load
+                                .push(aggregate(&interval_loads, &interval_weights));
 
-                            // set the new hourly time
-                            hourly_time = Some(iter_time);
-                            // clear the load vector and push the first one if not nan
-                            hourly_loads.clear();
-                            if !l.is_nan() {
-                                hourly_loads.push(*l)
-                            }
+                            // set the new time
+                            interval_time = Some(iter_time);
+                            // clear the load and weight vectors and push the first ones
+                            interval_loads.clear();
+                            interval_weights.clear();
+                            interval_loads.push(*l);
+                            interval_weights.push(*w);
 
                         }
                     },
                     None => {
-                        hourly_time = Some(iter_time);
-                        if !l.is_nan() {
-                            hourly_loads.push(*l)
-                        }
+                        interval_time = Some(iter_time);
+                        interval_loads.push(*l);
+                        interval_weights.push(*w);
 
                     },
                 }
             });
         
-        // finish by pushing the last hourly time and mean load
-        let hourly_mean_load = mean_or_nan(&hourly_loads);
-        hourly_timeload.time.push(hourly_time.unwrap());
-        hourly_timeload.load.push(hourly_mean_load);
+        // finish by pushing the last time and aggregated load
+        interval_timeload.time.push(interval_time.unwrap());
+        interval_timeload.load.push(aggregate(&interval_loads, &interval_weights));
+
+        Ok(interval_timeload)
+    }
+
+    /// Aggregate to daily data with the mean of the finite loads of each calendar day (NAN if none),
+    /// from local midnight to local midnight in the given timezone, labeled at local midnight.
+    /// See to_daily_from for days starting at a different time.
+    pub fn to_daily(&self, tz: FixedOffset) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.to_daily_from(tz, NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+    }
 
-        Ok(hourly_timeload)
+    /// Aggregate to daily data with the mean of the finite loads of each day (NAN if none).
+    /// The days are in the given timezone and start at the given time,
+    /// e.g., 07:00 for a hydrological day, and are labeled by their start datetime.
+    /// The time series is expected to be ordered.
+    pub fn to_daily_from(
+        &self,
+        tz: FixedOffset,
+        day_start: NaiveTime,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.daily_aggregate(tz, day_start, &mean_or_nan)
+    }
+
+    /// Daily change of the load, i.e., the last minus the first finite load of each calendar day,
+    /// in the given timezone and labeled at local midnight, e.g., for the daily water balance.
+    /// Days starting or ending with NAN use their first and last finite loads,
+    /// days with less than two finite loads are NAN.
+    pub fn daily_delta(&self, tz: FixedOffset) -> Result<TimeLoad, EmptyTimeLoad> {
+        let delta = |loads: &Vec<f64>| {
+            if loads.len() < 2 {
+                f64::NAN
+            } else {
+                loads[loads.len() - 1] - loads[0]
+            }
+        };
+        self.daily_aggregate(tz, NaiveTime::from_hms_opt(0, 0, 0).unwrap(), &delta)
+    }
+
+    /// Aggregate to weekly data with the mean of the finite loads of each ISO week (NAN if none),
+    /// from Monday to Monday at local midnight in the given timezone, labeled at the start.
+    /// The time series is expected to be ordered.
+    pub fn to_weekly(&self, tz: FixedOffset) -> Result<TimeLoad, EmptyTimeLoad> {
+        let week_start = |dt: NaiveDateTime| {
+            let monday = dt.date() - chrono::Days::new(dt.weekday().num_days_from_monday() as u64);
+            monday.and_time(NaiveTime::MIN)
+        };
+        self.period_aggregate(tz, &week_start, &mean_or_nan)
+    }
+
+    /// Aggregate to monthly data with the mean of the finite loads of each calendar month
+    /// (NAN if none), in the given timezone and labeled at local midnight of the first day.
+    /// The months have their calendar length, e.g., 28 to 31 days.
+    /// The time series is expected to be ordered.
+    pub fn to_monthly(&self, tz: FixedOffset) -> Result<TimeLoad, EmptyTimeLoad> {
+        let month_start = |dt: NaiveDateTime| {
+            dt.date().with_day(1).unwrap().and_time(NaiveTime::MIN)
+        };
+        self.period_aggregate(tz, &month_start, &mean_or_nan)
+    }
+
+    // Split the time series in days, as described in to_daily_from,
+    // and aggregate the finite loads of each day with the given function.
+    fn daily_aggregate(
+        &self,
+        tz: FixedOffset,
+        day_start: NaiveTime,
+        aggregate: &dyn Fn(&Vec<f64>) -> f64,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        let day_shift = day_start - NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+        let day = |dt: NaiveDateTime| (dt - day_shift).date().and_time(day_start);
+        self.period_aggregate(tz, &day, aggregate)
+    }
+
+    // Split the time series in calendar periods and aggregate the finite loads of each period.
+    // The period start maps each local datetime, in the given timezone,
+    // to the local start of its period, which is also the label of the period.
+    fn period_aggregate(
+        &self,
+        tz: FixedOffset,
+        period_start: &dyn Fn(NaiveDateTime) -> NaiveDateTime,
+        aggregate: &dyn Fn(&Vec<f64>) -> f64,
+    ) -> Result<TimeLoad, EmptyTimeLoad> {
+        self.debug_assert_consistent();
+        if self.time.is_empty() {
+            return Err(EmptyTimeLoad {});
+        }
+
+        let mut period_timeload = TimeLoad::new(self.time.len() / 1440 + 1);
+        let mut period: Option<NaiveDateTime> = None;
+        let mut period_loads: Vec<f64> = Vec::with_capacity(1440);
+
+        let push_period = |p: NaiveDateTime, loads: &Vec<f64>, periods: &mut TimeLoad| {
+            periods.time.push(tz.from_local_datetime(&p).unwrap());
+            periods.load.push(aggregate(loads));
+        };
+
+        for (t, l) in self.time.iter().zip(self.load.iter()) {
+            let iter_period = period_start(t.with_timezone(&tz).naive_local());
+            match period {
+                Some(p) if p == iter_period => {}
+                Some(p) => {
+                    push_period(p, &period_loads, &mut period_timeload);
+                    period_loads.clear();
+                    period = Some(iter_period);
+                }
+                None => period = Some(iter_period),
+            }
+            if !l.is_nan() {
+                period_loads.push(*l)
+            }
+        }
+        push_period(period.unwrap(), &period_loads, &mut period_timeload);
+
+        Ok(period_timeload)
     }
 
     /// Replace all values measured within the time interval with NANs.
     /// Given in standard time, fixed offset for the chosen timezone.
+    /// The bounds are included, and if time_init is after time_stop
+    /// the interval spans midnight, e.g., 23:30 to 00:30.
     pub fn replace_bad_time_interval_with_nan(
         &mut self,
         time_init: NaiveTime,
         time_stop: NaiveTime,
     ) {
+        self.replace_bad_time_interval(time_init, time_stop, BadIntervalMode::Nan);
+    }
+
+    /// Replace all values measured within the daily time interval according to the mode:
+    /// set them to NAN, or linearly interpolate them from the closest valid values before and
+    /// after each masked period, which keeps the series continuous for short daily intervals.
+    /// Masked periods without a valid value on both sides are left NAN.
+    /// Given in standard time, fixed offset for the chosen timezone.
+    pub fn replace_bad_time_interval(
+        &mut self,
+        time_init: NaiveTime,
+        time_stop: NaiveTime,
+        mode: BadIntervalMode,
+    ) {
+        self.debug_assert_consistent();
+        let mut masked: Vec<usize> = Vec::new();
         self.time
             .iter()
             .zip(self.load.iter_mut())
-            .for_each(|(t, l)| {
-                if (t.time() > time_init) & (t.time() < time_stop) {
+            .enumerate()
+            .for_each(|(i, (t, l))| {
+                let tt = t.time();
+                let in_interval = if time_init <= time_stop {
+                    (tt >= time_init) & (tt <= time_stop)
+                } else {
+                    // the interval spans midnight, e.g., 23:30 to 00:30
+                    (tt >= time_init) | (tt <= time_stop)
+                };
+                if in_interval {
                     *l = f64::NAN;
+                    masked.push(i);
                 }
             });
+        if let BadIntervalMode::LinearInterpolate = mode {
+            self.interpolate_indices(&masked);
+        }
+    }
+
+    /// Linearly interpolate the loads at the given sorted indices, in time,
+    /// from the closest finite loads before and after each run of consecutive indices.
+    fn interpolate_indices(&mut self, indices: &[usize]) {
+        // indices of the finite loads, searched with a binary search for each run
+        let finite: Vec<usize> =
+            (0..self.load.len()).filter(|i| self.load[*i].is_finite()).collect();
+        for (s, e) in index_runs(indices) {
+            let k0 = finite.partition_point(|i| *i < s);
+            let k1 = finite.partition_point(|i| *i <= e);
+            if (k0 == 0) || (k1 == finite.len()) {
+                continue;
+            }
+            let (t0, l0) = (self.time[finite[k0 - 1]], self.load[finite[k0 - 1]]);
+            let (t1, l1) = (self.time[finite[k1]], self.load[finite[k1]]);
+            let span = (t1 - t0).num_milliseconds() as f64;
+            for i in s..=e {
+                let frac = (self.time[i] - t0).num_milliseconds() as f64 / span;
+                self.load[i] = l0 + (l1 - l0) * frac;
+            }
+        }
+    }
+
+    /// Resample the time series at the target datetimes, e.g., those of another sensor,
+    /// linearly interpolating in time between the closest finite loads before and after.
+    /// A target is NAN if it is out of the time series or in a gap longer than max_gap,
+    /// i.e., the two finite loads are more than max_gap apart.
+    /// The time series is expected to be ordered, the targets can be in any order.
+    pub fn resample_to(
+        &self,
+        target_times: &[DateTime<FixedOffset>],
+        max_gap: chrono::Duration,
+    ) -> TimeLoad {
+        self.debug_assert_consistent();
+        let finite: Vec<(DateTime<FixedOffset>, f64)> = self
+            .time
+            .iter()
+            .zip(self.load.iter())
+            .filter(|(_, l)| l.is_finite())
+            .map(|(t, l)| (*t, *l))
+            .collect();
+        let mut timeload = TimeLoad::new(target_times.len());
+        for t in target_times.iter() {
+            let i = finite.partition_point(|(ft, _)| ft < t);
+            let load = if (i < finite.len()) && (finite[i].0 == *t) {
+                finite[i].1
+            } else if (i == 0) || (i == finite.len()) {
+                f64::NAN
+            } else {
+                let (t0, l0) = finite[i - 1];
+                let (t1, l1) = finite[i];
+                if t1 - t0 > max_gap {
+                    f64::NAN
+                } else {
+                    let span = (t1 - t0).num_milliseconds() as f64;
+                    let frac = (*t - t0).num_milliseconds() as f64 / span;
+                    l0 + (l1 - l0) * frac
+                }
+            };
+            timeload.push(*t, load);
+        }
+        timeload
+    }
+
+    /// Remove the average daily shape, e.g., the diurnal cycle of a temperature-sensitive cell.
+    /// The shape is the mean of the finite loads for each minute of the day, in the given timezone,
+    /// minus the overall mean, which is thus kept. Minutes without finite loads are skipped.
+    pub fn remove_diurnal(&mut self, tz: FixedOffset) {
+        self.debug_assert_consistent();
+        let minutes: Vec<usize> = self
+            .time
+            .iter()
+            .map(|t| {
+                let local = t.with_timezone(&tz);
+                (local.hour() * 60 + local.minute()) as usize
+            })
+            .collect();
+        let mut bins: Vec<(f64, usize)> = vec![(0., 0); 1440];
+        for (m, l) in minutes.iter().zip(self.load.iter()).filter(|(_, l)| l.is_finite()) {
+            bins[*m].0 += l;
+            bins[*m].1 += 1;
+        }
+        let (sum, count) = bins
+            .iter()
+            .fold((0., 0usize), |(s, c), b| (s + b.0, c + b.1));
+        if count == 0 {
+            return;
+        }
+        let mean = sum / count as f64;
+        for (m, l) in minutes.iter().zip(self.load.iter_mut()) {
+            let (bin_sum, bin_count) = bins[*m];
+            if bin_count > 0 {
+                *l -= bin_sum / bin_count as f64 - mean;
+            }
+        }
+    }
+
+    /// Convert the loads, e.g., from the raw counts of the logger to kg,
+    /// as load = slope * load + intercept. The NANs are left as NAN.
+    /// See calibrate_from_points to get slope and intercept from two reference points.
+    pub fn apply_calibration(&mut self, slope: f64, intercept: f64) {
+        self.load.iter_mut().for_each(|l| *l = slope * *l + intercept);
     }
 
     /// Set to NAN all the load values that are out of the expected range.
     pub fn replace_outliers_with_nan(&mut self, min_load: f64, max_load: f64) {
         self.load.iter_mut().for_each(|l| {
             if (*l > max_load) | (*l < min_load) {
-                println!(
+                eprintln!(
                     "setting to NAN value out of range (min: {}, max {}): {}",
                     min_load, max_load, l
                 );
@@ -257,49 +1835,520 @@ impl TimeLoad {
         });
     }
 
+    /// Set to NAN the outliers found with the rolling median absolute deviation,
+    /// i.e., the values farther than threshold times the MAD from the median of their window.
+    /// Unlike the fixed range of replace_outliers_with_nan, this follows a drifting baseline.
+    pub fn replace_outliers_mad(&mut self, window: usize, threshold: f64) {
+        let outliers = find_outliers_mad(&self.load, window, threshold);
+        for i in outliers.iter() {
+            eprintln!(
+                "setting to NAN value beyond {} MAD from the rolling median: {}",
+                threshold, self.load[*i]
+            );
+        }
+        setnan_by_index(&mut self.load, &outliers);
+    }
+
+    /// Set to NAN the values out of the Tukey's fences (see iqr_fences) of their rolling window,
+    /// centered and of the given odd width, shrinking at the edges.
+    /// The windows with less than min_len finite values are skipped.
+    pub fn replace_tukey_outliers_with_nan(&mut self, window: usize, min_len: usize, k: f64) {
+        assert!(
+            window % 2 == 1,
+            "the Tukey window has an even number of elements; \
+            it should be odd to have a central element"
+        );
+        let side = (window - 1) / 2;
+        let n = self.load.len();
+        let mut outliers: Vec<usize> = Vec::new();
+        for i in 0..n {
+            let vw = &self.load[i.saturating_sub(side)..std::cmp::min(i + side + 1, n)];
+            if let Ok((lower, upper)) = iqr_fences(vw, min_len, k) {
+                if (self.load[i] < lower) | (self.load[i] > upper) {
+                    eprintln!(
+                        "setting to NAN value out of the Tukey's fences ({}, {}): {}",
+                        lower, upper, self.load[i]
+                    );
+                    outliers.push(i);
+                }
+            }
+        }
+        setnan_by_index(&mut self.load, &outliers);
+    }
+
+    /// Count the error codes of each kind, with the default ERROR_FLT_* codes,
+    /// e.g., for a QA report before replacing them with NAN. Kinds without errors are absent.
+    pub fn error_summary(&self) -> HashMap<ErrorKind, usize> {
+        self.error_summary_with_codes(&ErrorCodes::default())
+    }
+
+    /// As error_summary, with the given error codes.
+    pub fn error_summary_with_codes(&self, codes: &ErrorCodes) -> HashMap<ErrorKind, usize> {
+        let mut summary: HashMap<ErrorKind, usize> = HashMap::new();
+        for kind in self.load.iter().filter_map(|l| codes.kind(*l)) {
+            *summary.entry(kind).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// Replace the error codes of the logger with NAN.
+    pub fn replace_error_codes_with_nan(&mut self, codes: &ErrorCodes) {
+        self.load.iter_mut().for_each(|l| {
+            if codes.is_error_code(*l) {
+                eprintln!("found error code: {}", l);
+                *l = f64::NAN;
+            }
+        });
+    }
+
     /// Consider all the values > max_value as invalid and replace them with NAN.
-    /// These high values are reserved for the errors.
+    /// These high values are reserved for the errors, see also replace_error_codes_with_nan.
     pub fn replace_errors_with_nan(&mut self, max_value: f64) {
         self.load.iter_mut().for_each(|l| {
             if *l > max_value {
-                println!("found invalid value: {}", l);
+                eprintln!("found invalid value: {}", l);
                 *l = f64::NAN;
             }
         });
     }
 
+    /// Fit a linear trend to the finite loads against the time in days from the first datetime.
+    /// Return the slope (load per day) and the intercept (load at the first datetime).
+    /// A large slope flags a drift of the baseline, e.g., a mechanical or calibration problem.
+    pub fn linear_trend(&self) -> (f64, f64) {
+        let days = self.days_from_start();
+        linear_fit(&days, &self.load)
+    }
+
+    /// Subtract the linear trend of the finite loads, e.g., the drift of the empty baseline,
+    /// and return its slope and intercept as linear_trend.
+    /// The loads are left unchanged if the trend can not be fitted (NAN slope and intercept).
+    pub fn detrend_linear(&mut self) -> (f64, f64) {
+        let (slope, intercept) = self.linear_trend();
+        let days = self.days_from_start();
+        self.subtract_trend(&days, slope, intercept);
+        (slope, intercept)
+    }
+
+    /// Subtract the linear trend fitted to a reference series instead, e.g., an empty platform
+    /// logged alongside, and return its slope and intercept as linear_trend of the reference.
+    /// The trend is evaluated at the datetimes of this series, in days from the reference start.
+    /// The loads are left unchanged if the trend can not be fitted (NAN slope and intercept).
+    pub fn detrend_with_reference(&mut self, reference: &TimeLoad) -> (f64, f64) {
+        let (slope, intercept) = reference.linear_trend();
+        if let Some(t0) = reference.time.first() {
+            let days = self.days_from(*t0);
+            self.subtract_trend(&days, slope, intercept);
+        }
+        (slope, intercept)
+    }
+
+    // Subtract slope * day + intercept from the loads, unless the trend is not finite.
+    fn subtract_trend(&mut self, days: &[f64], slope: f64, intercept: f64) {
+        if !slope.is_finite() || !intercept.is_finite() {
+            return;
+        }
+        self.load
+            .iter_mut()
+            .zip(days.iter())
+            .for_each(|(l, d)| *l -= slope * d + intercept);
+    }
+
+    /// Time in days from the first datetime, used as abscissa for the fits.
+    fn days_from_start(&self) -> Vec<f64> {
+        match self.time.first() {
+            Some(t0) => self.days_from(*t0),
+            None => Vec::new(),
+        }
+    }
+
+    /// Time in days from the given datetime, negative before it.
+    fn days_from(&self, t0: DateTime<FixedOffset>) -> Vec<f64> {
+        self.time
+            .iter()
+            .map(|t| (*t - t0).num_seconds() as f64 / 86400.)
+            .collect()
+    }
+
+    /// Remove the steps found with find_steps, i.e., the permanent shifts of the baseline,
+    /// by subtracting the magnitude of each step from the loads after it.
+    /// Return the datetime (first after the step) and magnitude of each removed step,
+    /// the magnitudes are those of the loads before any correction.
+    pub fn remove_steps(
+        &mut self,
+        window: usize,
+        min_step: f64,
+    ) -> Vec<(DateTime<FixedOffset>, f64)> {
+        self.debug_assert_consistent();
+        let steps: Vec<(usize, f64)> = find_steps(&self.load, window, min_step)
+            .into_iter()
+            .map(|i| (i, step_magnitude(&self.load, i, window)))
+            .collect();
+        for (i, magnitude) in steps.iter() {
+            self.load[*i..].iter_mut().for_each(|l| *l -= magnitude);
+        }
+        steps.into_iter().map(|(i, m)| (self.time[i], m)).collect()
+    }
+
+    /// Cumulative sum of the loads, e.g., to integrate the rate_of_change.
+    /// The NANs are skipped and carry the last sum forward, the leading NANs stay NAN.
+    pub fn cumulative(&self) -> TimeLoad {
+        let mut sum = f64::NAN;
+        let load: Vec<f64> = self
+            .load
+            .iter()
+            .map(|l| {
+                if !l.is_nan() {
+                    sum = if sum.is_nan() { *l } else { sum + l };
+                }
+                sum
+            })
+            .collect();
+        TimeLoad {
+            time: self.time.clone(),
+            load,
+        }
+    }
+
+    /// Rate of change of the load per hour, e.g., kg/hour,
+    /// (load[i + 1] - load[i]) / hours between the two datetimes.
+    /// The rates are labeled with the later datetime, thus the first datetime is dropped;
+    /// a NAN on either side gives a NAN rate.
+    pub fn rate_of_change(&self) -> TimeLoad {
+        let mut rate_timeload = TimeLoad::new(self.time.len().saturating_sub(1));
+        for (tw, lw) in self.time.windows(2).zip(self.load.windows(2)) {
+            let hours = (tw[1] - tw[0]).num_milliseconds() as f64 / 3_600_000.;
+            rate_timeload.time.push(tw[1]);
+            rate_timeload.load.push((lw[1] - lw[0]) / hours);
+        }
+        rate_timeload
+    }
+
+    /// Run the anomaly, step, and flatline detections and return a single list of events,
+    /// ordered by start datetime.
+    /// The flagged indices of each detector are merged into events of consecutive indices,
+    /// so that overlapping windows give a single event.
+    /// The overlapping events of different detectors are also merged into one event,
+    /// with the kind of highest priority (step, anomaly, flatline) and the load range
+    /// over the merged event as magnitude.
+    pub fn events(&self, config: &EventConfig) -> Vec<Event> {
+        // kind, first and last index (inclusive), and magnitude of each detected event
+        let mut ranges: Vec<(EventKind, usize, usize, f64)> = Vec::new();
+        if config.anomaly_width > 0 {
+            let (indices, _) = find_anomalies(
+                &self.load,
+                config.anomaly_width,
+                config.anomaly_width / 2usize,
+                config.anomaly_iqr,
+            );
+            for (s, e) in index_runs(&indices) {
+                let magnitude = self.range_event(EventKind::Anomaly, s, e).magnitude;
+                ranges.push((EventKind::Anomaly, s, e, magnitude));
+            }
+        }
+        if config.step_width > 0 {
+            for i in find_steps(&self.load, config.step_width, config.min_step) {
+                let magnitude = step_magnitude(&self.load, i, config.step_width).abs();
+                ranges.push((EventKind::Step, i - 1, i, magnitude));
+            }
+        }
+        if config.flatline_min_len > 0 {
+            let mut s = 0usize;
+            for i in 1..=self.load.len() {
+                if (i < self.load.len()) && (self.load[i] == self.load[s]) {
+                    continue;
+                }
+                if (i - s >= config.flatline_min_len) && self.load[s].is_finite() {
+                    ranges.push((EventKind::Flatline, s, i - 1, self.load[s]));
+                }
+                s = i;
+            }
+        }
+        ranges.sort_by_key(|r| r.1);
+        let mut merged: Vec<(EventKind, usize, usize, f64)> = Vec::new();
+        for r in ranges.into_iter() {
+            match merged.last_mut() {
+                Some(m) if r.1 <= m.2 => {
+                    m.0 = std::cmp::max_by_key(m.0, r.0, |k| k.priority());
+                    m.2 = std::cmp::max(m.2, r.2);
+                    m.3 = f64::NAN;
+                }
+                _ => merged.push(r),
+            }
+        }
+        merged
+            .into_iter()
+            .map(|(kind, s, e, magnitude)| {
+                if magnitude.is_nan() {
+                    self.range_event(kind, s, e)
+                } else {
+                    Event {
+                        kind,
+                        start: self.time[s],
+                        end: self.time[e],
+                        magnitude,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Event of the given kind between the indices s and e (inclusive),
+    /// with the load range as magnitude.
+    fn range_event(&self, kind: EventKind, s: usize, e: usize) -> Event {
+        let (min, max) = self.load[s..=e]
+            .iter()
+            .filter(|l| l.is_finite())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), l| {
+                (min.min(*l), max.max(*l))
+            });
+        Event {
+            kind,
+            start: self.time[s],
+            end: self.time[e],
+            magnitude: if min <= max { max - min } else { f64::NAN },
+        }
+    }
+
     /// Write the datetime and load columns to a csv file at the given path.
     /// Use RFC 3339 - ISO 8601 for datetime.
     /// %Y-%m-%dT%H:%M:%S%z
-    pub fn to_csv<P>(self, fout: P)
+    pub fn to_csv<P>(&self, fout: P)
+    where
+        P: AsRef<Path>,
+    {
+        self.to_csv_with_header(fout, "datetime,load_kg", None);
+    }
+
+    /// As to_csv, but with the given header line, e.g., "timestamp,load_lb" for other units,
+    /// and optionally with the given number of decimals for the loads,
+    /// e.g., to match the resolution of the sensor.
+    pub fn to_csv_with_header<P>(&self, fout: P, header: &str, precision: Option<usize>)
     where
         P: AsRef<Path>,
     {
+        self.debug_assert_consistent();
         let file = File::create(fout).unwrap();
         let mut buf = BufWriter::new(file);
-        buf.write_all("datetime,load_kg\n".as_bytes()).unwrap();
+        buf.write_all(format!("{}\n", header).as_bytes()).unwrap();
+        for (t, w) in self.time.iter().zip(self.load.iter()) {
+            let line = match precision {
+                Some(p) => format!("{},{:.*}\n", t.to_rfc3339(), p, w),
+                None => format!("{},{}\n", t.to_rfc3339(), w),
+            };
+            buf.write_all(line.as_bytes()).unwrap();
+        }
+    }
+
+    /// Append the rows to a csv file at the given path, e.g., to accumulate new samples
+    /// without rewriting the whole file.
+    /// If the file does not exist or is empty, it is created with the header of to_csv,
+    /// otherwise the header is assumed to be there and only the rows are written.
+    pub fn append_to_csv<P>(&self, fout: P)
+    where
+        P: AsRef<Path>,
+    {
+        self.debug_assert_consistent();
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(fout)
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+        // do not merge the first row with an unterminated last line
+        let mut last = [b'\n'; 1];
+        if len > 0 {
+            file.seek(SeekFrom::End(-1)).unwrap();
+            file.read_exact(&mut last).unwrap();
+        }
+        let mut buf = BufWriter::new(file);
+        if len == 0 {
+            buf.write_all("datetime,load_kg\n".as_bytes()).unwrap();
+        } else if last[0] != b'\n' {
+            buf.write_all("\n".as_bytes()).unwrap();
+        }
         for (t, w) in self.time.iter().zip(self.load.iter()) {
             buf.write_all(format!("{},{}\n", t.to_rfc3339(), w).as_bytes())
                 .unwrap();
         }
     }
 
-    /// Plot the load time series to svg.
-    // pub fn plot_datetime<P>(&self, fout: P) -> Result<(), Box<dyn std::error::Error>>
-    // where
-    //     P: AsRef<Path>,
-    // {
-    //     let (xmin, xmax) = min_and_max(self.time.iter());
-    //     let xspan: chrono::Duration = xmax - xmin;
-    //     let xfmt = suitable_xfmt(xspan);
-    //     let (ymin, ymax) = min_and_max(self.load.iter().filter(|x| !x.is_nan()));
-    //     let yspan = (ymax - ymin) / 10f64;
-    //     let ymin = ymin - yspan;
-    //     let ymax = ymax + yspan;
-    //     let root = SVGBackend::new(&fout, (1600, 800)).into_drawing_area();
-    //     root.fill(&WHITE)?;
-    //     let mut chart = ChartBuilder::on(&root)
-    //         .margin(50)
+    /// Write the time series to a json file at the given path,
+    /// as {"time": [...], "load": [...]} with the datetimes in RFC3339 and NAN as null.
+    pub fn to_json<P>(&self, fout: P) -> Result<(), Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        self.debug_assert_consistent();
+        let json = TimeLoadJson {
+            time: self.time.iter().map(|t| t.to_rfc3339()).collect(),
+            load: self.load.clone(),
+        };
+        let buf = BufWriter::new(File::create(fout)?);
+        serde_json::to_writer(buf, &json)?;
+        Ok(())
+    }
+
+    /// Read a time series from a json file written by to_json, null loads are read as NAN.
+    pub fn from_json<P>(fin: P) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let buf = BufReader::new(File::open(fin)?);
+        let json: TimeLoadJson = serde_json::from_reader(buf)?;
+        let time = json
+            .time
+            .iter()
+            .map(|t| DateTime::parse_from_rfc3339(t))
+            .collect::<Result<Vec<DateTime<FixedOffset>>, _>>()?;
+        Ok(TimeLoad::from_vecs(time, json.load)?)
+    }
+
+    /// Write the time series to a parquet file at the given path, e.g., for large archives,
+    /// with a datetime column (timestamp in milliseconds) and a load_kg column (f64, NAN kept).
+    /// The timestamps are stored in UTC with the offset of the first datetime as timezone.
+    #[cfg(feature = "parquet")]
+    pub fn to_parquet<P>(&self, fout: P) -> Result<(), Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        use arrow_array::{Float64Array, RecordBatch, TimestampMillisecondArray};
+        use arrow_schema::{DataType, Field, Schema, TimeUnit};
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let tz: String = match self.time.first() {
+            Some(t) => t.offset().to_string(),
+            None => String::from("+00:00"),
+        };
+        let millis: Vec<i64> = self.time.iter().map(|t| t.timestamp_millis()).collect();
+        let time = TimestampMillisecondArray::from(millis).with_timezone(tz.clone());
+        let load = Float64Array::from(self.load.clone());
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "datetime",
+                DataType::Timestamp(TimeUnit::Millisecond, Some(tz.into())),
+                false,
+            ),
+            Field::new("load_kg", DataType::Float64, false),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(time), Arc::new(load)])?;
+        let mut writer = ArrowWriter::try_new(File::create(fout)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    /// Write the time series as to_csv, compressed with gzip, e.g., to loadcells.csv.gz.
+    #[cfg(feature = "gzip")]
+    pub fn to_csv_gz<P>(&self, fout: P) -> Result<(), Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        self.debug_assert_consistent();
+        let mut gz = GzEncoder::new(BufWriter::new(File::create(fout)?), Compression::default());
+        gz.write_all("datetime,load_kg\n".as_bytes())?;
+        for (t, w) in self.time.iter().zip(self.load.iter()) {
+            gz.write_all(format!("{},{}\n", t.to_rfc3339(), w).as_bytes())?;
+        }
+        gz.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Read a time series from a parquet file written by to_parquet.
+    /// The datetimes are given the offset stored as timezone of the datetime column.
+    #[cfg(feature = "parquet")]
+    pub fn from_parquet<P>(fin: P) -> Result<TimeLoad, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        use arrow_array::{Array, Float64Array, TimestampMillisecondArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(fin)?)?.build()?;
+        let mut timeload = TimeLoad::new(0);
+        for batch in reader {
+            let batch = batch?;
+            let time = batch
+                .column_by_name("datetime")
+                .and_then(|c| c.as_any().downcast_ref::<TimestampMillisecondArray>())
+                .ok_or("missing datetime column of timestamps in milliseconds")?;
+            let load = batch
+                .column_by_name("load_kg")
+                .and_then(|c| c.as_any().downcast_ref::<Float64Array>())
+                .ok_or("missing load_kg column of f64")?;
+            let offset: FixedOffset = match time.timezone() {
+                Some(tz) => tz.parse()?,
+                None => FixedOffset::east_opt(0).unwrap(),
+            };
+            for (t, l) in time.values().iter().zip(load.values().iter()) {
+                let t = DateTime::from_timestamp_millis(*t).ok_or("timestamp out of range")?;
+                timeload.time.push(t.with_timezone(&offset));
+                timeload.load.push(*l);
+            }
+        }
+        Ok(timeload)
+    }
+
+    /// Write the datetime, load, and raw reading columns to a csv file at the given path,
+    /// i.e., the same three columns written by load_log.
+    /// The raw readings are matched by datetime (see from_csv_with_raw),
+    /// thus the loads set to NAN keep their original raw reading, e.g., the logger error,
+    /// while the raw reading is left blank for the datetimes without one (e.g., filled values).
+    pub fn to_csv_with_raw<P>(&self, fout: P, raw_readings: &[RawReading])
+    where
+        P: AsRef<Path>,
+    {
+        self.debug_assert_consistent();
+        let raw: HashMap<&DateTime<FixedOffset>, &String> =
+            raw_readings.iter().map(|(t, r)| (t, r)).collect();
+        let file = File::create(fout).unwrap();
+        let mut buf = BufWriter::new(file);
+        buf.write_all("datetime,load_kg,raw_reading\n".as_bytes()).unwrap();
+        for (t, w) in self.time.iter().zip(self.load.iter()) {
+            let r = raw.get(t).map(|r| r.as_str()).unwrap_or("");
+            buf.write_all(format!("{},{},{}\n", t.to_rfc3339(), w, r).as_bytes())
+                .unwrap();
+        }
+    }
+
+    /// Write the datetime, load, and quality columns to a csv file at the given path,
+    /// the quality must have one element per load, see quality_flags.
+    pub fn to_csv_with_quality<P>(&self, fout: P, quality: &[Quality])
+    where
+        P: AsRef<Path>,
+    {
+        self.debug_assert_consistent();
+        assert_eq!(quality.len(), self.load.len(), "quality and load have different lengths");
+        let file = File::create(fout).unwrap();
+        let mut buf = BufWriter::new(file);
+        buf.write_all("datetime,load_kg,quality\n".as_bytes()).unwrap();
+        for ((t, w), q) in self.time.iter().zip(self.load.iter()).zip(quality.iter()) {
+            buf.write_all(format!("{},{},{}\n", t.to_rfc3339(), w, q).as_bytes())
+                .unwrap();
+        }
+    }
+
+    /// Plot the load time series to svg.
+    // pub fn plot_datetime<P>(&self, fout: P) -> Result<(), Box<dyn std::error::Error>>
+    // where
+    //     P: AsRef<Path>,
+    // {
+    //     let (xmin, xmax) = min_and_max(self.time.iter());
+    //     let xspan: chrono::Duration = xmax - xmin;
+    //     let xfmt = suitable_xfmt(xspan);
+    //     let (ymin, ymax) = min_and_max(self.load.iter().filter(|x| !x.is_nan()));
+    //     let yspan = (ymax - ymin) / 10f64;
+    //     let ymin = ymin - yspan;
+    //     let ymax = ymax + yspan;
+    //     let root = SVGBackend::new(&fout, (1600, 800)).into_drawing_area();
+    //     root.fill(&WHITE)?;
+    //     let mut chart = ChartBuilder::on(&root)
+    //         .margin(50)
     //         .x_label_area_size(40)
     //         .y_label_area_size(100)
     //         .build_cartesian_2d(xmin.clone()..xmax.clone(), ymin..ymax)?;
@@ -332,22 +2381,340 @@ impl TimeLoad {
     //     Ok(())
     // }
 
-    pub fn plotly_plot_datetime<P>(&self, _fout: P) -> Result<(), Box<dyn std::error::Error>>
+    /// Plot the load time series with plotly and write it to the given path,
+    /// the format (html, svg, or png) is chosen from the extension, see PlotFormat.
+    /// Open the plot in the browser only if show is true, e.g., not on headless machines.
+    pub fn plotly_plot_datetime<P>(
+        &self,
+        fout: P,
+        show: bool,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        self.plotly_plot_datetime_with_range(fout, show, None)
+    }
+
+    /// As plotly_plot_datetime, with the given y range, e.g., to compare several plots,
+    /// or, if None, the finite range of the loads padded by PLOT_Y_PAD_PCT (see plot_y_range).
+    pub fn plotly_plot_datetime_with_range<P>(
+        &self,
+        fout: P,
+        show: bool,
+        y_range: Option<(f64, f64)>,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let y_range = y_range.or(self.plot_y_range(PLOT_Y_PAD_PCT));
+        let mut plot = Plot::new(); 
+        plot.add_trace(self.plotly_trace());
+        plot.set_layout(TimeLoad::plotly_layout(self.time_span(), y_range));
+        TimeLoad::plotly_write(&plot, fout, show)
+    }
+
+    /// Downsample for plotting to at most max_points with Largest-Triangle-Three-Buckets,
+    /// which preserves the visual shape, unlike the plain decimation or the averages.
+    /// Each run of finite loads is downsampled separately, with its share of the points,
+    /// and the runs are separated by a NAN so that the gaps are still drawn as breaks.
+    /// The separators count towards max_points; if even the first and last point of each run
+    /// do not fit, the shortest runs are dropped, i.e., drawn as part of the gaps.
+    /// Return a clone if there are no more than max_points.
+    pub fn downsample_for_plot(&self, max_points: usize) -> TimeLoad {
+        self.debug_assert_consistent();
+        if self.len() <= max_points {
+            return self.clone();
+        }
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut i = 0usize;
+        while i < self.len() {
+            if self.load[i].is_nan() {
+                i += 1;
+                continue;
+            }
+            let end = (i..self.len()).find(|j| self.load[*j].is_nan()).unwrap_or(self.len());
+            runs.push((i, end));
+            i = end;
+        }
+        // the first and last point of each run, and a separator between the runs
+        let least = |runs: &[(usize, usize)]| {
+            runs.iter().map(|(s, e)| (e - s).min(2)).sum::<usize>() + runs.len().saturating_sub(1)
+        };
+        while !runs.is_empty() && least(&runs) > max_points {
+            let shortest = (0..runs.len()).min_by_key(|r| runs[*r].1 - runs[*r].0).unwrap();
+            runs.remove(shortest);
+        }
+        let spare = max_points.saturating_sub(least(&runs));
+        let finite: usize = runs.iter().map(|(s, e)| e - s).sum::<usize>().max(1);
+        let mut dtl = TimeLoad::new(max_points);
+        for (r, (s, e)) in runs.iter().enumerate() {
+            if r > 0 {
+                // one NAN for the whole gap, at its first datetime
+                dtl.push(self.time[runs[r - 1].1], f64::NAN);
+            }
+            let share = (e - s).min(2) + spare * (e - s) / finite;
+            let x: Vec<f64> = self.time[*s..*e]
+                .iter()
+                .map(|t| t.timestamp_millis() as f64)
+                .collect();
+            for j in lttb_indices(&x, &self.load[*s..*e], share) {
+                dtl.push(self.time[s + j], self.load[s + j]);
+            }
+        }
+        dtl
+    }
+
+    /// Histogram of the finite loads, see histogram,
+    /// e.g., a bimodal distribution may reveal a stuck sensor.
+    pub fn load_histogram(&self, bins: usize) -> Vec<(f64, f64, usize)> {
+        histogram(&self.load, bins)
+    }
+
+    /// Summary statistics of the loads, the time span, and the sampling interval,
+    /// taken as the smallest time step as in fill_missing_with_nan, e.g., for a quick QA.
+    pub fn stats(&self) -> LoadStats {
+        self.debug_assert_consistent();
+        let mut finite: Vec<f64> = self.load.iter().copied().filter(|l| l.is_finite()).collect();
+        let n = finite.len();
+        let (min, max) = match min_and_max_finite(&finite) {
+            Some(((_, min), (_, max))) => (min, max),
+            None => (f64::NAN, f64::NAN),
+        };
+        let mean = mean_or_nan(&finite);
+        let std = if n > 1 {
+            (finite.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt()
+        } else {
+            f64::NAN
+        };
+        let (q25, q75, iqr) = calculate_iqr(&finite, 2).unwrap_or((f64::NAN, f64::NAN, f64::NAN));
+        let median = if n > 0 { median_in_place(&mut finite) } else { f64::NAN };
+        LoadStats {
+            count: self.load.len(),
+            finite_count: n,
+            nan_count: self.load.iter().filter(|l| l.is_nan()).count(),
+            min,
+            max,
+            mean,
+            median,
+            std,
+            q25,
+            q75,
+            iqr,
+            time_span: self.time_span(),
+            sampling_interval: if self.time.len() > 1 { Some(self.min_delta()) } else { None },
+        }
+    }
+
+    /// The time from the first to the last datetime, zero if there are fewer than two.
+    /// The time series is expected to be ordered.
+    pub fn time_span(&self) -> chrono::Duration {
+        match (self.time.first(), self.time.last()) {
+            (Some(first), Some(last)) => *last - *first,
+            _ => chrono::Duration::zero(),
+        }
+    }
+
+    /// The finite range of the loads padded by pad_pct of the span on each side,
+    /// or of the value if the loads are constant. None without finite loads.
+    pub fn plot_y_range(&self, pad_pct: f64) -> Option<(f64, f64)> {
+        let ((_, min), (_, max)) = min_and_max_finite(&self.load)?;
+        Some(TimeLoad::padded_range(min, max, pad_pct))
+    }
+
+    fn padded_range(min: f64, max: f64, pad_pct: f64) -> (f64, f64) {
+        let span = if max > min { max - min } else { max.abs().max(1.) };
+        let pad = span * pad_pct / 100.;
+        (min - pad, max + pad)
+    }
+
+    /// Plot this and the other time series on the same axes, with a legend,
+    /// e.g., the raw and the smoothed loads to check the processing.
+    /// Each trace uses its own datetimes, so the two time series can have different lengths.
+    /// The output is as for plotly_plot_datetime.
+    pub fn plotly_plot_overlay<P>(
+        &self,
+        other: &TimeLoad,
+        fout: P,
+        name_self: &str,
+        name_other: &str,
+        show: bool,
+    ) -> Result<(), Box<dyn std::error::Error>>
     where
         P: AsRef<Path>,
     {
+        // the y range should include both time series
+        let both: Vec<f64> = self.load.iter().chain(other.load.iter()).copied().collect();
+        let y_range = min_and_max_finite(&both)
+            .map(|((_, min), (_, max))| TimeLoad::padded_range(min, max, PLOT_Y_PAD_PCT));
+        let first = self.time.first().min(other.time.first());
+        let last = self.time.last().max(other.time.last());
+        let x_span = match (first, last) {
+            (Some(first), Some(last)) => *last - *first,
+            _ => chrono::Duration::zero(),
+        };
         let mut plot = Plot::new(); 
-        let trace = Scatter::new(self.time.iter().map(|t| t.to_rfc3339()).collect(), self.load.clone());
-        plot.add_trace(trace);
+        plot.add_trace(self.plotly_trace().name(name_self));
+        plot.add_trace(other.plotly_trace().name(name_other));
+        plot.set_layout(TimeLoad::plotly_layout(x_span, y_range).show_legend(true));
+        TimeLoad::plotly_write(&plot, fout, show)
+    }
+
+    /// Write the plot in the format given by the extension,
+    /// the static images (svg and png) are exported with kaleido.
+    fn plotly_write<P>(plot: &Plot, fout: P, show: bool) -> Result<(), Box<dyn std::error::Error>>
+    where
+        P: AsRef<Path>,
+    {
+        match PlotFormat::from_path(&fout) {
+            Some(PlotFormat::Html) => plot.write_html(&fout),
+            Some(PlotFormat::Svg) => plot.write_image(&fout, ImageFormat::SVG, 1600, 800, 1.0),
+            Some(PlotFormat::Png) => plot.write_image(&fout, ImageFormat::PNG, 1600, 800, 1.0),
+            None => {
+                return Err(format!(
+                    "unsupported plot format {:?}, use html, svg, or png",
+                    fout.as_ref()
+                )
+                .into())
+            }
+        }
+        if show {
+            plot.show();
+        }
+        Ok(())
+    }
+
+    /// Scatter trace of the time series,
+    /// the NANs become missing points (null) and are drawn as breaks in the line.
+    /// Long time series are downsampled to PLOT_MAX_POINTS, see downsample_for_plot.
+    fn plotly_trace(&self) -> Box<Scatter<String, Option<f64>>> {
+        let tl = self.downsample_for_plot(PLOT_MAX_POINTS);
+        let load: Vec<Option<f64>> = tl
+            .load
+            .iter()
+            .map(|l| if l.is_nan() { None } else { Some(*l) })
+            .collect();
+        Scatter::new(tl.time.iter().map(|t| t.to_rfc3339()).collect(), load)
+            .connect_gaps(false)
+    }
+
+    /// Layout shared by the plotly plots, with the given y range or plotly auto-range if None.
+    /// The y ticks are integers, unless the range is too small for them;
+    /// the x ticks are formatted according to the time span, see suitable_xfmt.
+    fn plotly_layout(x_span: chrono::Duration, y_range: Option<(f64, f64)>) -> Layout {
         let background_color: Rgba = Rgba::new(200, 200, 200, 0.5);
-        let layout = Layout::new()
-        .x_axis(Axis::new().title(Title::new("Time")).zero_line(false).line_width(2).n_ticks(12))
-        .y_axis(Axis::new().title(Title::new("Load kg")).zero_line(false).tick_format("d").line_width(2))
+        let x_axis = Axis::new()
+            .title(Title::new("Time"))
+            .zero_line(false)
+            .line_width(2)
+            .n_ticks(12)
+            .tick_format(suitable_xfmt(x_span));
+        let mut y_axis = Axis::new().title(Title::new("Load kg")).zero_line(false).line_width(2);
+        y_axis = match y_range {
+            Some((min, max)) => {
+                let tick_format = match max - min {
+                    r if r < 5. => ".2f",
+                    r if r < 50. => ".1f",
+                    _ => "d",
+                };
+                y_axis.range(vec![min, max]).tick_format(tick_format)
+            }
+            None => y_axis.tick_format("d"),
+        };
+        Layout::new()
+        .x_axis(x_axis)
+        .y_axis(y_axis)
         .font(Font::new().size(16))
-        .plot_background_color(background_color);
-        plot.set_layout(layout);
-        plot.show();
-        Ok(())
+        .plot_background_color(background_color)
+    }
+}
+
+// The json layout of TimeLoad, see to_json and from_json.
+#[derive(Serialize, Deserialize)]
+struct TimeLoadJson {
+    time: Vec<String>,
+    #[serde(serialize_with = "serialize_nan_as_null")]
+    #[serde(deserialize_with = "deserialize_null_as_nan")]
+    load: Vec<f64>,
+}
+
+// Json has no NAN, write the NANs as null.
+fn serialize_nan_as_null<S>(load: &[f64], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let load: Vec<Option<f64>> = load
+        .iter()
+        .map(|l| if l.is_nan() { None } else { Some(*l) })
+        .collect();
+    load.serialize(serializer)
+}
+
+// Read the null loads back as NAN.
+fn deserialize_null_as_nan<'de, D>(deserializer: D) -> Result<Vec<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let load: Vec<Option<f64>> = Vec::deserialize(deserializer)?;
+    Ok(load.into_iter().map(|l| l.unwrap_or(f64::NAN)).collect())
+}
+
+/// What TimeLoad::validate_csv found in a csv file, from at most CSV_SAMPLE_ROWS data rows.
+/// The datetime format is None for RFC 3339 (see from_csv),
+/// otherwise one of CSV_DATETIME_FORMATS (see from_csv_with_format).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvInfo {
+    pub header: Vec<String>,
+    pub columns: usize,
+    pub sampled_rows: usize,
+    pub datetime_format: Option<&'static str>,
+}
+
+/// Summary statistics of a time series, see TimeLoad::stats.
+/// The load statistics are over the finite loads, NAN if there are none,
+/// the std is the sample standard deviation (NAN with fewer than two finite loads),
+/// and the quartiles are as in calculate_iqr.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadStats {
+    pub count: usize,
+    pub finite_count: usize,
+    pub nan_count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub std: f64,
+    pub q25: f64,
+    pub q75: f64,
+    pub iqr: f64,
+    pub time_span: chrono::Duration,
+    pub sampling_interval: Option<chrono::Duration>,
+}
+
+impl std::fmt::Display for LoadStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "count {}", self.count)?;
+        writeln!(f, "finite {}", self.finite_count)?;
+        writeln!(f, "nan {}", self.nan_count)?;
+        writeln!(f, "min {}", self.min)?;
+        writeln!(f, "max {}", self.max)?;
+        writeln!(f, "mean {}", self.mean)?;
+        writeln!(f, "median {}", self.median)?;
+        writeln!(f, "std {}", self.std)?;
+        writeln!(f, "q25 {}", self.q25)?;
+        writeln!(f, "q75 {}", self.q75)?;
+        writeln!(f, "iqr {}", self.iqr)?;
+        writeln!(f, "time_span {} s", self.time_span.num_seconds())?;
+        match self.sampling_interval {
+            Some(d) => write!(f, "sampling_interval {} s", d.num_seconds()),
+            None => write!(f, "sampling_interval unknown"),
+        }
+    }
+}
+
+impl FromIterator<(DateTime<FixedOffset>, f64)> for TimeLoad {
+    fn from_iter<I: IntoIterator<Item = (DateTime<FixedOffset>, f64)>>(iter: I) -> Self {
+        let (time, load) = iter.into_iter().unzip();
+        TimeLoad { time, load }
     }
 }
 
@@ -361,6 +2728,33 @@ impl std::fmt::Display for TimeLoad {
     }
 }
 
+/// Write the events to a csv file at the given path, e.g., for the field report.
+/// Use RFC 3339 - ISO 8601 for the start and end datetimes,
+/// the duration is in seconds.
+pub fn events_to_csv<P>(events: &[Event], fout: P)
+where
+    P: AsRef<Path>,
+{
+    let file = File::create(fout).unwrap();
+    let mut buf = BufWriter::new(file);
+    buf.write_all("kind,start,end,duration_s,magnitude\n".as_bytes())
+        .unwrap();
+    for e in events.iter() {
+        buf.write_all(
+            format!(
+                "{},{},{},{},{}\n",
+                e.kind,
+                e.start.to_rfc3339(),
+                e.end.to_rfc3339(),
+                (e.end - e.start).num_seconds(),
+                e.magnitude
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    }
+}
+
 // use crate::utils::compare_vecf64;
 // Run the tests with:
 // cargo test -- --nocapture
@@ -396,193 +2790,1781 @@ mod tests {
             dtstr_pst, dtiso_pst, dtfix_pst
         );
 
-        // assert that DST goes 1 hour back
-        let timediff = chrono::Duration::hours(1);
-        assert!(dtfix_pst - timediff == dtfix_dst);
+        // assert that DST goes 1 hour back
+        let timediff = chrono::Duration::hours(1);
+        assert!(dtfix_pst - timediff == dtfix_dst);
+    }
+
+    #[test]
+    // Get the reading datetime with the correct offset
+    fn test_get_current_datetime_offset() {
+        let dtnow: DateTime<Local> = Local::now();
+        println!(
+            "local time is {}",
+            dtnow.to_rfc3339_opts(SecondsFormat::Secs, false)
+        );
+        let local_offset = dtnow.offset();
+        println!("local offet is {}", local_offset);
+    }
+
+    #[test]
+    // Assert that a homogenous load time series gives no anomalies
+    fn test_find_anomaly_homogeneous() {
+        let a = [5.0f64; 15];
+        let expected: Vec<f64> = Vec::new();
+        let (_, anomalies_load) = find_anomalies(&a, 7usize, 6usize, 5.0f64);
+        assert!(anomalies_load == expected);
+    }
+
+    #[test]
+    // Assert that a linear load time series with small increment gives no anomalies
+    fn test_find_anomaly_linear() {
+        let v: Vec<f64> = (1..15).map(|n| n as f64).collect();
+        let expected: Vec<f64> = Vec::new();
+        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
+        assert!(anomalies_load == expected);
+    }
+
+    #[test]
+    // Assert that a NANs are correctly handled while finding anomalies
+    fn test_find_anomaly_nans() {
+        let mut v: Vec<f64> = (1..15).map(|n| n as f64).collect();
+        v.iter_mut().enumerate().for_each(|(i, e)| {
+            if i < 6usize {
+                *e = f64::NAN
+            }
+        });
+        let expected: Vec<f64> = Vec::new();
+        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
+        assert!(anomalies_load == expected);
+    }
+
+    #[test]
+    // Assert that the anomalies are correctly identified by adding a big discontinuity
+    fn test_find_anomaly_discontinuity() {
+        let mut v: Vec<f64> = (1..15).map(|n| n as f64).collect();
+        v.iter_mut().enumerate().for_each(|(i, e)| {
+            if i < 8usize {
+                *e = 20.
+            }
+        });
+        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
+        let expected: Vec<f64> = vec![20.0, 20.0, 20.0, 20.0, 9.0, 10.0, 11.0, 12.0, 13.0];
+        assert!(anomalies_load == expected);
+    }
+
+    #[test]
+    // Assert that the detailed reports give the quartiles and cover the same anomalous indices
+    fn test_find_anomaly_detailed() {
+        let mut v: Vec<f64> = (1..15).map(|n| n as f64).collect();
+        v.iter_mut().enumerate().for_each(|(i, e)| {
+            if i < 8usize {
+                *e = 20.
+            }
+        });
+        let reports = find_anomalies_detailed(&v, 7usize, 6usize, 5.0f64);
+        assert!(!reports.is_empty());
+        for r in reports.iter() {
+            assert!(r.indices.start == r.window_start);
+            assert!(r.indices.len() == 7usize);
+            assert!(compare_f64_approx(r.qu - r.ql, r.iqr, 1e-9));
+            assert!(r.iqr > 5.0f64);
+        }
+        let mut indices: Vec<usize> = reports.iter().flat_map(|r| r.indices.clone()).collect();
+        indices.sort();
+        indices.dedup();
+        let (anomalies_index, _) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
+        assert!(indices == anomalies_index);
+    }
+
+    #[test]
+    // Assert that fast oscillations within the IQR threshold are found only on the derivative
+    fn test_find_anomaly_on_derivative() {
+        let mut v: Vec<f64> = vec![10.0f64; 30];
+        v.iter_mut().enumerate().for_each(|(i, e)| {
+            if (12usize..18usize).contains(&i) & (i % 2 == 1) {
+                *e = 30.
+            }
+        });
+        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 25.0f64);
+        assert!(anomalies_load.is_empty());
+        let (anomalies_index, _) = find_anomalies_on_derivative(&v, 7usize, 6usize, 25.0f64);
+        assert!(anomalies_index.contains(&13usize));
+        assert!(anomalies_index.windows(2).all(|w| w[1] > w[0]));
+        assert!(*anomalies_index.last().unwrap() < v.len());
+    }
+
+    #[test]
+    // Compare the rolling sum with the hand-summed windows, skipping NANs with a minimum count
+    fn test_rolling_sum() {
+        let v = vec![1., 2., f64::NAN, 4., f64::NAN, f64::NAN, 7.];
+        let rs = rolling_sum(&v, 3usize, 2usize);
+        let expected = vec![f64::NAN, f64::NAN, 3., 6., f64::NAN, f64::NAN, f64::NAN];
+        assert!(compare_vecf64_exact(&rs, &expected));
+        let rs = rolling_sum(&v, 3usize, 1usize);
+        let expected = vec![f64::NAN, f64::NAN, 3., 6., 4., 4., 7.];
+        assert!(compare_vecf64_exact(&rs, &expected));
+    }
+
+    #[test]
+    // The rolling median removes the spike, shrinks at the edges, and requires half finite values
+    fn test_rolling_median() {
+        let v = vec![1., 2., 100., 3., 4., f64::NAN, f64::NAN, f64::NAN, 5.];
+        let rm = rolling_median(&v, 3usize);
+        let expected = vec![1.5, 2., 3., 4., 3.5, f64::NAN, f64::NAN, f64::NAN, 5.];
+        assert!(compare_vecf64_exact(&rm, &expected));
+    }
+
+    #[test]
+    // The cumulative sum carries over the NANs, the daily delta uses the first and last finite
+    // loads of each day
+    fn test_cumulative_daily_delta() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T22:00:00-08:00,\n\
+            2021-10-13T23:00:00-08:00,1\n\
+            2021-10-14T00:00:00-08:00,2\n\
+            2021-10-14T01:00:00-08:00,\n\
+            2021-10-14T02:00:00-08:00,4\n\
+            2021-10-14T03:00:00-08:00,\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let cumulative = tl.cumulative();
+        assert!(cumulative.time == tl.time);
+        let expected = vec![f64::NAN, 1., 3., 3., 7., 7.];
+        assert!(compare_vecf64_exact(&cumulative.load, &expected));
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let delta = tl.daily_delta(tz).unwrap();
+        let correct_times = vec![
+            DateTime::parse_from_rfc3339("2021-10-13T00:00:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-14T00:00:00-08:00").unwrap(),
+        ];
+        assert!(delta.time == correct_times);
+        assert!(compare_vecf64_exact(&delta.load, &[f64::NAN, 2.]));
+    }
+
+    #[test]
+    // The rate is per hour with the real time steps, labeled with the later datetime
+    fn test_rate_of_change() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,13000\n\
+            2021-10-13T23:30:00-08:00,13001\n\
+            2021-10-14T01:30:00-08:00,13005\n\
+            2021-10-14T02:30:00-08:00,\n\
+            2021-10-14T03:30:00-08:00,13006\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let rate = tl.rate_of_change();
+        assert!(rate.time == tl.time[1..]);
+        let expected = vec![2., 2., f64::NAN, f64::NAN];
+        assert!(compare_vecf64_exact(&rate.load, &expected));
+    }
+
+    #[test]
+    // The NANs carry the smoothed value forward, ewma_time matches ewma for a regular step
+    fn test_ewma() {
+        let v = vec![f64::NAN, 1., 3., f64::NAN, 5.];
+        let expected = vec![f64::NAN, 1., 2., 2., 3.5];
+        assert!(compare_vecf64_exact(&ewma(&v, 0.5), &expected));
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let time: Vec<DateTime<FixedOffset>> =
+            (0..5).map(|i| t0 + chrono::Duration::minutes(i)).collect();
+        let v = vec![1., 3., 2., 4., 5.];
+        let tau = chrono::Duration::minutes(2);
+        let alpha = 1. - (-0.5f64).exp();
+        assert!(compare_vecf64_approx(&ewma_time(&time, &v, tau), &ewma(&v, alpha)));
+        // after a missing value, the next one weights the time since the last finite value
+        let v = vec![1., f64::NAN, 3.];
+        let smooth = ewma_time(&time[..3], &v, tau);
+        let alpha = 1. - (-1f64).exp();
+        assert!(compare_f64_approx(smooth[2], alpha * 3. + (1. - alpha) * 1., 1E-9));
+    }
+
+    #[test]
+    // The gaussian and asymmetric windows are odd with the central weight at the middle
+    fn test_make_window_gaussian_asymmetric() {
+        let w = make_window_gaussian(1., 2usize);
+        assert!(w.len() == 5);
+        assert!(compare_f64_exact(w[2], 1.));
+        assert!(compare_f64_exact(w[0], w[4]));
+        assert!(compare_f64_exact(w[1], (-0.5f64).exp()));
+        let w = make_window_asymmetric(3., 1., 0., 2usize, 0usize);
+        assert!(compare_vecf64_exact(&w, &[1., 2., 3., 0., 0.]));
+        let w = make_window_asymmetric(3., 1., 2., 1usize, 2usize);
+        assert!(compare_vecf64_exact(&w, &[0., 1., 3., 2.5, 2.]));
+        assert!(compare_vecf64_exact(
+            &make_window_asymmetric(3., 1., 1., 2usize, 2usize),
+            &make_window(3., 1., 2usize)
+        ));
+    }
+
+    #[test]
+    // Shrink and Reflect keep the ends that Nan loses, a constant series stays constant
+    fn test_mavg_with_edges() {
+        let w = make_window(3., 1., 2usize);
+        let v = vec![5.; 6];
+        let nan_edges = mavg_with_edges(&v, &w, 0usize, 0., EdgeMode::Nan);
+        assert!(nan_edges[0].is_nan() & nan_edges[5].is_nan());
+        assert!(compare_vecf64_exact(&nan_edges, &mavg(&v, &w, 0usize, 0.)));
+        let shrink = mavg_with_edges(&v, &w, 0usize, 0., EdgeMode::Shrink);
+        assert!(compare_vecf64_exact(&shrink, &v));
+        let reflect = mavg_with_edges(&v, &w, 0usize, 0., EdgeMode::Reflect);
+        assert!(compare_vecf64_exact(&reflect, &v));
+        // the reflected window at the start is [3, 2, 1, 2, 3] with weights [1, 2, 3, 2, 1]
+        let v = vec![1., 2., 3., 4., 5., 6.];
+        let reflect = mavg_with_edges(&v, &w, 0usize, 0., EdgeMode::Reflect);
+        assert!(compare_f64_exact(reflect[0], 17. / 9.));
+        let shrink = mavg_with_edges(&v, &w, 0usize, 0., EdgeMode::Shrink);
+        assert!(compare_f64_exact(shrink[0], 10. / 6.));
+    }
+
+    #[test]
+    // The automatic choice gives the output of mavg for both short and long inputs
+    fn test_mavg_auto() {
+        let w = make_window(3., 1., 2usize);
+        let mut v: Vec<f64> = (0..MAVG_PARALLEL_MIN_WORK / 4).map(|i| (i % 7) as f64).collect();
+        setnan_by_index(&mut v, &[3, 4, 10, 11, 12, 1000]);
+        let expected = mavg(&v, &w, 2usize, 50.);
+        assert!(compare_vecf64_exact(&mavg_auto(&v, &w, 2usize, 50.), &expected));
+        let expected = mavg(&v[..100], &w, 2usize, 50.);
+        assert!(compare_vecf64_exact(&mavg_auto(&v[..100], &w, 2usize, 50.), &expected));
+    }
+
+    #[test]
+    // The streaming rolling mean matches mavg, delayed by side and without the last side values
+    fn test_rolling_mean() {
+        let v = vec![1., 2., f64::NAN, 4., 5., f64::NAN, f64::NAN, f64::NAN, 9., 10., 11.];
+        let w = make_window(3., 1., 2usize);
+        let expected = mavg(&v, &w, 2usize, 50.);
+        let mut rm = RollingMean::new(w, 2usize, 50.);
+        let streamed: Vec<f64> = v.iter().filter_map(|x| rm.push(*x)).collect();
+        assert!(compare_vecf64_exact(&streamed, &expected[..v.len() - 2]));
+    }
+
+    #[test]
+    // The MAD outliers follow the drifting baseline, where a fixed range would fail
+    fn test_replace_outliers_mad() {
+        let mut tl = TimeLoad::new(20);
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        for i in 0..20 {
+            tl.time.push(t0 + chrono::Duration::minutes(i));
+            tl.load.push(13000. + 10. * i as f64 + (i % 3) as f64);
+        }
+        tl.load[5] += 150.;
+        tl.load[15] = f64::NAN;
+        tl.replace_outliers_mad(5, 5.);
+        let nan_indices: Vec<usize> = (0..20).filter(|i| tl.load[*i].is_nan()).collect();
+        assert!(nan_indices == [5, 15]);
+    }
+
+    #[test]
+    // The fences are ql - k * iqr and qu + k * iqr, the outliers are set to NAN per window
+    fn test_tukey_outliers() {
+        let v = vec![1., 2., 3., 4., 5.];
+        let (lower, upper) = iqr_fences(&v, 5usize, TUKEY_K).unwrap();
+        assert!(compare_f64_exact(lower, -1.));
+        assert!(compare_f64_exact(upper, 7.));
+        assert!(iqr_fences(&v, 6usize, TUKEY_K).is_err());
+        let mut tl = TimeLoad::new(12);
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        for i in 0..12 {
+            tl.time.push(t0 + chrono::Duration::minutes(i));
+            tl.load.push(13000. + (i % 4) as f64);
+        }
+        tl.load[6] = 13100.;
+        tl.replace_tukey_outliers_with_nan(7, 6, TUKEY_K);
+        let nan_indices: Vec<usize> = (0..12).filter(|i| tl.load[*i].is_nan()).collect();
+        assert!(nan_indices == [6]);
+    }
+
+    #[test]
+    // Deduplicate removes consecutive repeated elements,
+    // thus if the input is sorted dedup returns no duplicates
+    fn test_deduplicate_load_series() {
+        let mut v_all = vec![1., 2., 2., 2., 3., 4., 4., 5., 6., 6.];
+        let v_unique = vec![1., 2., 3., 4., 5., 6.];
+        let v_duplicates = vec![2., 2., 4., 6.];
+
+        v_all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let (dedup, duplicates) = v_all.partition_dedup_by(|a, b| a == b);
+
+        let mut duplicates = duplicates.to_owned();
+        duplicates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        // println!("test:\ndedup {:?} == {:?}?\nduplicates {:?} == {:?}?", dedup, v_unique, duplicates, v_duplicates);
+        assert!(v_unique == dedup);
+        assert!(v_duplicates == duplicates);
+    }
+
+    #[test]
+    fn test_discharge_by_index() {
+        let vall: Vec<f64> = (1..20).map(|n| n as f64).collect();
+        let indices: Vec<usize> = vec![2, 3, 7, 12, 13, 18];
+        let mut expected: Vec<f64> = (1..20).map(|n| n as f64).collect();
+        for i in indices.iter().rev() {
+            expected.remove(*i);
+        }
+        let vout = discharge_by_index(&vall, &indices);
+        println!("vout\n{:?}\nexpected\n{:?}", vout, expected);
+        assert!(compare_vecf64_approx(&vout, &expected));
+        let vout = discharge_by_index(&vall, &[]);
+        assert!(compare_vecf64_approx(&vout, &vall));
+    }
+
+    #[test]
+    // Time and load are removed together and keep the same length
+    fn test_discharge_indices() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let mut tl =
+            TimeLoad::from_pairs((0..10).map(|i| (t0 + chrono::Duration::minutes(i), i as f64)));
+        tl.discharge_indices(&[7, 2, 3]);
+        assert!(tl.time.len() == tl.load.len());
+        assert!(tl.validate().is_ok());
+        assert!(compare_vecf64_exact(&tl.load, &[0., 1., 4., 5., 6., 8., 9.]));
+        assert!(tl.time[2] == t0 + chrono::Duration::minutes(4));
+    }
+
+    #[test]
+    fn test_setnan_by_index() {
+        let mut vall: Vec<f64> = (1..20).map(|n| n as f64).collect();
+        let indices: Vec<usize> = vec![2, 3, 7, 12, 13, 18];
+        let mut expected: Vec<f64> = (1..20).map(|n| n as f64).collect();
+        indices.iter().for_each(|i| expected[*i] = f64::NAN);
+        setnan_by_index(&mut vall, &indices);
+        assert!(compare_vecf64_approx(&vall, &expected));
+    }
+
+    #[test]
+    // full processing test, including all the optional steps
+    fn test_all_steps() {
+        // define time zone for the test
+        let mut timezone: i32 = -8;
+        timezone *= 60 * 60;
+        let timezone_fixed_offset = FixedOffset::east_opt(timezone).unwrap();
+
+        // read the time series and adjust to the deifned time zone
+        let mut tl = TimeLoad::from_csv(String::from("./test/timeload_raw.csv")).unwrap();
+        tl.time
+            .iter_mut()
+            .for_each(|t| *t = t.with_timezone(&timezone_fixed_offset));
+        println!("{}", tl);
+
+        // make sure the time series is ordered before processing
+        tl.is_ordered();
+
+        // make continuous by filling missing values with NANs, then assert continuity
+        let mut ctl = tl.fill_missing_with_nan();
+        ctl.is_ordered_and_continuous();
+        println!("{}", ctl);
+
+        // read bad datetimes and replace them with NANs
+        let bad = read_bad_datetimes("./test/bad_datetimes.csv").unwrap();
+        ctl.replace_bad_datetimes_with_nan(bad);
+        println!("{}", ctl);
+
+        // define a daily interval over which values should be ignored (maintenance, etc.),
+        // then these intervals to NAN
+        let time_init = NaiveTime::parse_from_str("01:02", "%H:%M").unwrap();
+        let time_stop = NaiveTime::parse_from_str("01:05", "%H:%M").unwrap();
+        ctl.replace_bad_time_interval_with_nan(time_init, time_stop);
+        println!("{}", ctl);
+
+        // replace errors with NANs, in this case all the values above 99995.
+        ctl.replace_errors_with_nan(99995.);
+        println!("{}", ctl);
+
+        // keep only load values within a specific range, set the outliers to NAN
+        ctl.replace_outliers_with_nan(10000., 18000.);
+        println!("{}", ctl);
+
+        // find anomalies and set to NAN
+        let (anomalies_indices, _) = find_anomalies(&ctl.load, 16usize, 8usize, 40.0f64);
+        println!("indices of the anomalies:\n{:?}", anomalies_indices);
+        let mut atl = TimeLoad::new(anomalies_indices.len());
+        for i in anomalies_indices.iter() {
+            atl.time.push(ctl.time.get(*i).unwrap().clone());
+            atl.load.push(ctl.load.get(*i).unwrap().clone());
+        }
+        atl.to_csv("./test/timeload_anomalies.csv");
+
+        setnan_by_index(&mut ctl.load[..], &anomalies_indices);
+
+        // apply a weighted moving average to smooth the filtered time series
+        let mavg_window = make_window(3., 1., 5usize);
+        let smooth = mavg(&ctl.load[..], &mavg_window, 5 as usize, 80.);
+        println!("{:?}", smooth);
+
+        // the parallel version gives the same moving average
+        let smooth_parallel = mavg_parallel(&ctl.load[..], &mavg_window, 5usize, 80.);
+        assert!(compare_vecf64_exact(&smooth, &smooth_parallel));
+
+        // in this case simply replace the original load series with the smooth one;
+        // if preferred keep both and compare
+        ctl.load = smooth;
+
+        // plot the filtered and smooth load series
+        ctl.plotly_plot_datetime("./test/timeload_processed.html", false).unwrap();
+
+        // save the filtered and smooth load series
+        ctl.to_csv("./test/timeload_processed.csv");
+    }
+
+    #[test]
+    fn test_to_hourly() {
+        let tl = TimeLoad::from_csv(String::from("./test/short_for_hourly.csv")).unwrap();
+        let htl = &tl
+            .to_hourly(NanPolicy::Ignore { min_valid: 1 })
+            .expect("empty data set");
+        let correct_hourly_loads = vec![1.0f64, 1.5f64];
+        let correct_hourly_times = vec![
+            DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-14T00:00:00-08:00").unwrap(),
+        ];
+        assert! {compare_vecf64_approx(&htl.load, &correct_hourly_loads)};
+        assert! {htl.time == correct_hourly_times};
+    }
+
+    #[test]
+    // The plot format is chosen from the extension, unsupported extensions give None
+    fn test_plot_format_from_path() {
+        assert!(PlotFormat::from_path("load.svg") == Some(PlotFormat::Svg));
+        assert!(PlotFormat::from_path("./test/load.PNG") == Some(PlotFormat::Png));
+        assert!(PlotFormat::from_path("load.html") == Some(PlotFormat::Html));
+        assert!(PlotFormat::from_path("load.pdf").is_none());
+        assert!(PlotFormat::from_path("load").is_none());
+    }
+
+    #[test]
+    // Downsample to 15 minutes, rounding each datetime to the nearest step
+    fn test_to_interval() {
+        let tl = TimeLoad::from_csv(String::from("./test/short_for_hourly.csv")).unwrap();
+        let qtl = &tl
+            .to_interval(chrono::Duration::minutes(15), NanPolicy::Ignore { min_valid: 1 })
+            .expect("empty data set");
+        let correct_loads = vec![1.3f64, 2.0f64];
+        let correct_times = vec![
+            DateTime::parse_from_rfc3339("2021-10-13T23:30:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-13T23:45:00-08:00").unwrap(),
+        ];
+        assert! {compare_vecf64_approx(&qtl.load, &correct_loads)};
+        assert! {qtl.time == correct_times};
+    }
+
+    #[test]
+    // Downsample to 15 minutes propagating or ignoring the NANs, with a minimum of valid loads
+    fn test_downsample_nan_policy() {
+        let tl = TimeLoad::from_csv(String::from("./test/short_for_hourly.csv")).unwrap();
+        let step = chrono::Duration::minutes(15);
+        let expected = [
+            (NanPolicy::Propagate, [1.3f64, f64::NAN]),
+            (NanPolicy::Ignore { min_valid: 0 }, [1.3f64, 2.0f64]),
+            (NanPolicy::Ignore { min_valid: 2 }, [1.3f64, 2.0f64]),
+            (NanPolicy::Ignore { min_valid: 3 }, [1.3f64, f64::NAN]),
+            (NanPolicy::Ignore { min_valid: 11 }, [f64::NAN, f64::NAN]),
+        ];
+        for (nan, loads) in expected.iter() {
+            let dtl = tl.to_interval(step, *nan).expect("empty data set");
+            assert!(compare_vecf64_approx(&dtl.load, loads), "{:?}", nan);
+        }
+        assert!(mean_ignore_nan(&[1., f64::NAN, 2.], 1) == 1.5);
+        assert!(mean_ignore_nan(&[1., f64::NAN, 2.], 3).is_nan());
+        assert!(mean_ignore_nan(&[f64::NAN], 0).is_nan());
+        assert!(mean_ignore_nan(&[], 0).is_nan());
+    }
+
+    #[test]
+    #[cfg(feature = "fft")]
+    // Diurnal cycle with a faster and smaller one, found despite a NAN and a gap
+    fn test_dominant_period() {
+        let t0 = DateTime::parse_from_rfc3339("2022-05-01T00:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(480);
+        for i in (0..480).filter(|i| (*i < 200) | (*i > 210)) {
+            let hours = i as f64 / 2.;
+            let load = 13000.
+                + 5. * (2. * std::f64::consts::PI * hours / 24.).sin()
+                + 1. * (2. * std::f64::consts::PI * hours / 6.).sin();
+            tl.push(t0 + chrono::Duration::minutes(30 * i), load);
+        }
+        tl.load[50] = f64::NAN;
+        let period = dominant_period(&tl.time, &tl.load).unwrap();
+        assert!(period == chrono::Duration::hours(24));
+        assert!(dominant_period(&tl.time[..3], &tl.load[..3]).is_none());
+        assert!(dominant_period(&tl.time, &vec![1.; tl.len()]).is_none());
+    }
+
+    #[test]
+    // The spike is replaced by its window median, the small noise and the NAN are kept
+    fn test_hampel() {
+        let v = vec![10., 10.2, 9.9, 10.1, 30., 10., f64::NAN, 9.8, 10.1, 10.];
+        let (filtered, replaced) = hampel(&v, 5, 3.);
+        assert!(replaced == vec![4]);
+        assert!((filtered[4] - 10.05).abs() < 1e-12);
+        assert!(compare_vecf64_exact(&filtered[5..], &v[5..]) & (filtered[..4] == v[..4]));
+        assert!(hampel(&v, 5, 200.).1.is_empty());
+    }
+
+    #[test]
+    // Trailing mean of the last three values, NAN at the start and filled from the past only
+    fn test_mavg_causal() {
+        let mut v: Vec<f64> = (1..=10).map(|e| e as f64).collect();
+        let smooth = mavg_causal(&v, &[1., 1., 1.], 0, 0.);
+        assert!(compare_vecf64_exact(&smooth[..3], &[f64::NAN, f64::NAN, 2.]));
+        assert!(smooth[9] == 9.);
+        v[5] = f64::NAN;
+        let smooth = mavg_causal(&v, &[1., 1., 1.], 1, 50.);
+        assert!((smooth[1] == 1.5) & (smooth[5] == 4.5) & (smooth[6] == 6.));
+        // the causal part of a centered window
+        let w = make_window(3., 1., 2);
+        assert!(mavg_causal(&v, &w[..=2], 0, 0.)[9] == (8. + 2. * 9. + 3. * 10.) / 6.);
+    }
+
+    #[test]
+    // Three days of the same daily shape, flattened to the overall mean
+    fn test_remove_diurnal() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t0 = tz.with_ymd_and_hms(2022, 5, 1, 0, 0, 0).unwrap();
+        let shape = |h: i64| if (8..20).contains(&(h % 24)) { 2. } else { -2. };
+        let mut tl = TimeLoad::from_pairs(
+            (0..72).map(|h| (t0 + chrono::Duration::hours(h), 100. + shape(h) + h as f64 / 72.)),
+        );
+        tl.load[10] = f64::NAN;
+        let mean = mean_ignore_nan(&tl.load, 1);
+        tl.remove_diurnal(tz);
+        assert!(tl.load[10].is_nan());
+        assert!((mean_ignore_nan(&tl.load, 1) - mean).abs() < 1e-9);
+        // only the trend is left, within a day of it
+        assert!(tl.load.iter().filter(|l| l.is_finite()).all(|l| (*l - mean).abs() < 1.));
+    }
+
+    #[test]
+    // Equal bins from the finite min to max, the max in the last bin, NANs skipped
+    fn test_histogram() {
+        let v = vec![0., 1., f64::NAN, 2.5, 9., 10., 10.];
+        let h = histogram(&v, 4);
+        assert!(h.iter().map(|b| b.2).collect::<Vec<usize>>() == vec![2, 1, 0, 3]);
+        assert!((h[0].0 == 0.) & (h[0].1 == 2.5) & (h[3].1 == 10.));
+        assert!(histogram(&[5., 5., f64::NAN], 4) == vec![(5., 5., 2)]);
+        assert!(histogram(&[f64::NAN], 4).is_empty() & histogram(&v, 0).is_empty());
+        let tl = TimeLoad::from_csv("./test/short_for_hourly.csv").unwrap();
+        let total: usize = tl.load_histogram(10).iter().map(|b| b.2).sum();
+        assert!(total == tl.load.iter().filter(|l| l.is_finite()).count());
+    }
+
+    #[test]
+    // Measured, filled by the moving average, still NAN, anomaly even if filled, and replaced
+    fn test_quality_flags() {
+        let before = vec![1., f64::NAN, 3., f64::NAN, f64::NAN, 6.];
+        let after = vec![1., 2., 3., f64::NAN, 5., 6.];
+        let quality = quality_flags(&before, &after, &[5], &[4]);
+        let expected = vec![
+            Quality::Measured,
+            Quality::Filled,
+            Quality::Measured,
+            Quality::Masked,
+            Quality::Anomaly,
+            Quality::Replaced,
+        ];
+        assert!(quality == expected);
+        assert!(format!("{}", Quality::Filled) == "filled");
+    }
+
+    #[test]
+    // The NAN'd error keeps its raw reading, the filled datetime has a blank one
+    fn test_to_csv_with_raw() {
+        let (mut tl, raw_readings) = TimeLoad::from_csv_with_raw("./test/logger_raw.csv").unwrap();
+        assert!((raw_readings.len() == 3) & (tl.len() == 3));
+        assert!(raw_readings[1] == (tl.time[1], String::from("E+999999.")));
+        tl.replace_error_codes_with_nan(&ErrorCodes::default());
+        let ftl = tl.fill_missing_with_nan();
+        ftl.to_csv_with_raw("./test/logger_raw_processed.csv", &raw_readings);
+        let lines: Vec<String> = std::fs::read_to_string("./test/logger_raw_processed.csv")
+            .unwrap()
+            .lines()
+            .map(|l| l.to_owned())
+            .collect();
+        assert!(lines[0] == "datetime,load_kg,raw_reading");
+        assert!(lines[1] == "2022-05-01T00:00:00-08:00,13001,  +13001.0");
+        assert!(lines[2] == "2022-05-01T00:01:00-08:00,NaN,E+999999.");
+        assert!(lines[3] == "2022-05-01T00:02:00-08:00,NaN,");
+    }
+
+    #[test]
+    // Read the matching daily files, ordered and without the duplicate at midnight
+    fn test_from_csv_dir() {
+        assert!(matches_wildcard("loads_2022-05-01.csv", "loads_*.csv"));
+        assert!(matches_wildcard("loads_2022-05-01.csv", "*_2022-05-0?.csv"));
+        assert!(!matches_wildcard("loads_2022-05-01.csv.gz", "loads_*.csv"));
+        let tl = TimeLoad::from_csv_dir("./test/daily", "loads_*.csv").unwrap();
+        tl.is_ordered();
+        assert!(tl.len() == 5);
+        assert!(compare_vecf64_exact(&tl.load, &[100., 101., 102., 103., f64::NAN]));
+        assert!(TimeLoad::from_csv_dir("./test/daily", "none_*.csv").is_err());
+    }
+
+    #[test]
+    // Slice from start included to end excluded, empty outside the data or for end before start
+    fn test_slice_time() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t = |h: u32| tz.with_ymd_and_hms(2022, 5, 1, h, 0, 0).unwrap();
+        let tl = TimeLoad::from_pairs((2..8).map(|h| (t(h), h as f64)));
+        assert!(tl.slice_time(t(3), t(5)).load == vec![3., 4.]);
+        assert!(tl.slice_time(t(0), t(23)).load == tl.load);
+        assert!(tl.slice_time(t(9), t(23)).is_empty());
+        assert!(tl.slice_time(t(5), t(3)).is_empty());
+    }
+
+    #[test]
+    // Split at the gaps longer than max_gap, a gap equal to max_gap is kept
+    fn test_split_at_gaps() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t0 = tz.with_ymd_and_hms(2022, 5, 1, 0, 0, 0).unwrap();
+        let minutes = [0, 1, 2, 4, 30, 31, 90];
+        let tl = TimeLoad::from_pairs(
+            minutes.iter().map(|m| (t0 + chrono::Duration::minutes(*m), *m as f64)),
+        );
+        let segments = tl.split_at_gaps(chrono::Duration::minutes(2));
+        let lens: Vec<usize> = segments.iter().map(|s| s.len()).collect();
+        assert!(lens == vec![4, 2, 1]);
+        assert!(segments[1].load == vec![30., 31.]);
+        segments.iter().for_each(|s| s.is_ordered());
+        assert!(TimeLoad::new(0).split_at_gaps(chrono::Duration::minutes(2)).is_empty());
+    }
+
+    #[test]
+    // Two steps, up and down, found at the first index after them and removed
+    fn test_steps() {
+        let mut v = vec![0.; 10];
+        v.extend(vec![5.; 10]);
+        v.extend(vec![2.; 10]);
+        v[4] = f64::NAN;
+        v[15] = 5.5;
+        assert!(find_steps(&v, 3, 1.) == vec![10, 20]);
+        assert!(find_steps(&v, 3, 6.).is_empty());
+        assert!(find_steps(&v[..5], 3, 1.).is_empty());
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t0 = tz.with_ymd_and_hms(2022, 5, 1, 0, 0, 0).unwrap();
+        let mut tl = TimeLoad::from_pairs(
+            v.iter().enumerate().map(|(i, l)| (t0 + chrono::Duration::minutes(i as i64), *l)),
+        );
+        let steps = tl.remove_steps(3, 1.);
+        assert!(steps == vec![(tl.time[10], 5.), (tl.time[20], -3.)]);
+        assert!(tl.load[4].is_nan() & (tl.load[15] == 0.5));
+        assert!(tl.load.iter().filter(|l| l.is_finite()).all(|l| (*l == 0.) | (*l == 0.5)));
+    }
+
+    #[test]
+    // Detrend on its own linear trend and on the trend of a reference series
+    fn test_detrend() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t = |d: u32| tz.with_ymd_and_hms(2022, 5, d, 0, 0, 0).unwrap();
+        let drift = |d: u32| 100. + 2. * (d as f64 - 1.);
+        let mut tl = TimeLoad::from_pairs((1..=5).map(|d| (t(d), drift(d))));
+        tl.load[2] = f64::NAN;
+        assert!(tl.detrend_linear() == (2., 100.));
+        assert!(compare_vecf64_exact(&tl.load, &[0., 0., f64::NAN, 0., 0.]));
+        // the reference starts a day earlier, the trend is evaluated on its days
+        let reference = TimeLoad::from_pairs((1..=5).map(|d| (t(d), drift(d))));
+        let mut tl = TimeLoad::from_pairs((2..=4).map(|d| (t(d), drift(d) + 10.)));
+        assert!(tl.detrend_with_reference(&reference) == (2., 100.));
+        assert!(tl.load == vec![10., 10., 10.]);
+        let mut tl = TimeLoad::from_pairs([(t(1), 5.)]);
+        assert!(tl.detrend_linear().0.is_nan() & (tl.load[0] == 5.));
+    }
+
+    #[test]
+    // Tare on the mean of the window, error and no change for an all-NAN window
+    fn test_tare() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t = |h: u32| tz.with_ymd_and_hms(2022, 5, 1, h, 0, 0).unwrap();
+        let mut tl = TimeLoad::from_pairs(
+            [12., f64::NAN, 14., 50., f64::NAN].iter().enumerate().map(|(h, l)| (t(h as u32), *l)),
+        );
+        assert!(tl.tare(t(4), t(6)).is_err());
+        assert!(tl.load[0] == 12.);
+        assert!(tl.tare(t(0), t(2)).unwrap() == 13.);
+        assert!(compare_vecf64_exact(&tl.load, &[-1., f64::NAN, 1., 37., f64::NAN]));
+    }
+
+    #[test]
+    // Calibration through two points, NANs untouched
+    fn test_calibration() {
+        let (slope, intercept) = calibrate_from_points((1000., 0.), (3000., 50.));
+        assert!((slope == 0.025) & (intercept == -25.));
+        assert!(calibrate_from_points((1000., 0.), (1000., 50.)).0.is_nan());
+        let mut tl = TimeLoad::new(3);
+        let tz = FixedOffset::east_opt(0).unwrap();
+        for (h, l) in [1000., f64::NAN, 3000.].iter().enumerate() {
+            tl.push(tz.with_ymd_and_hms(2022, 5, 1, h as u32, 0, 0).unwrap(), *l);
+        }
+        tl.apply_calibration(slope, intercept);
+        assert!(compare_vecf64_exact(&tl.load, &[0., f64::NAN, 50.]));
+    }
+
+    #[test]
+    // Weekly means from Monday, monthly means over the calendar months, NANs skipped
+    fn test_to_weekly_monthly() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        // daily loads from Thursday 2021-01-28 to Tuesday 2021-03-02
+        let start = tz.with_ymd_and_hms(2021, 1, 28, 12, 0, 0).unwrap();
+        let mut tl = TimeLoad::from_pairs(
+            (0..34).map(|d| (start + chrono::Duration::days(d), d as f64)),
+        );
+        tl.load[5] = f64::NAN;
+        let weekly = tl.to_weekly(tz).unwrap();
+        assert!(weekly.time[0] == tz.with_ymd_and_hms(2021, 1, 25, 0, 0, 0).unwrap());
+        assert!(weekly.time[1] == tz.with_ymd_and_hms(2021, 2, 1, 0, 0, 0).unwrap());
+        assert!(weekly.time.len() == 6);
+        assert!((weekly.load[0] == 1.5) & (weekly.load[1] == 44. / 6.));
+        assert!(weekly.load[5] == 32.5);
+        let monthly = tl.to_monthly(tz).unwrap();
+        let months: Vec<DateTime<FixedOffset>> = [1, 2, 3]
+            .iter()
+            .map(|m| tz.with_ymd_and_hms(2021, *m, 1, 0, 0, 0).unwrap())
+            .collect();
+        assert!(monthly.time == months);
+        // January 28 to 31, the 28 days of February, and March 1 and 2
+        assert!(monthly.load == vec![1.5, 485. / 27., 32.5]);
+    }
+
+    #[test]
+    // The stages of the processing arrive in order to a custom callback
+    fn test_process_stages() {
+        use crate::load_process::{run, ProcessConfig, ProcessStage};
+        let config = ProcessConfig {
+            csvin: std::path::PathBuf::from("./test/short_for_hourly.csv"),
+            csvout: std::path::PathBuf::from("./test/short_for_hourly_processed.csv"),
+            min_load: 0.,
+            quiet: true,
+            ..Default::default()
+        };
+        let seen = std::cell::RefCell::new(Vec::new());
+        let on_stage = |s: &ProcessStage| seen.borrow_mut().push(*s);
+        run(config, &on_stage).unwrap();
+        let seen = seen.into_inner();
+        // no anomaly stage without the anomaly detection
+        let indices: Vec<usize> = seen.iter().map(|s| s.index()).collect();
+        assert!(indices == vec![0, 1, 2, 4, 5]);
+        assert!(seen[0] == ProcessStage::Read { rows: 14 });
+        assert!(seen[4] == ProcessStage::Write { rows: 14 });
+        assert!(seen[2].to_string() == "masked, 2 nan of 14 rows");
+    }
+
+    #[test]
+    // The plain csv files are read as from_csv
+    fn test_from_csv_auto() {
+        let tl = TimeLoad::from_csv("./test/short_for_hourly.csv").unwrap();
+        let atl = TimeLoad::from_csv_auto("./test/short_for_hourly.csv").unwrap();
+        assert!((atl.time == tl.time) & compare_vecf64_approx(&atl.load, &tl.load));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    // Write and read back a gzip-compressed csv, which is also validated
+    fn test_csv_gz() {
+        let tl = TimeLoad::from_csv("./test/short_for_hourly.csv").unwrap();
+        let fout = std::env::temp_dir().join("load_lpp_test_csv_gz.csv.gz");
+        tl.to_csv_gz(&fout).unwrap();
+        let gtl = TimeLoad::from_csv_auto(&fout).unwrap();
+        assert!((gtl.time == tl.time) & compare_vecf64_approx(&gtl.load, &tl.load));
+        assert!(TimeLoad::validate_csv(&fout).unwrap().sampled_rows == CSV_SAMPLE_ROWS);
+    }
+
+    #[test]
+    // Accept the load csv files with their datetime format, reject the wrong files
+    fn test_validate_csv() {
+        let info = TimeLoad::validate_csv("./test/short_for_hourly.csv").unwrap();
+        assert!(info.header == vec!["datetime", "load_kg"]);
+        // only the first rows are read, the tail of the file is not validated
+        assert!((info.columns == 2) & (info.sampled_rows == CSV_SAMPLE_ROWS));
+        assert!(info.datetime_format.is_none());
+        assert!(ErrorCodes::is_code_str("E+999995.") & ErrorCodes::is_code_str("E-1."));
+        assert!(!ErrorCodes::is_code_str("Error") & !ErrorCodes::is_code_str("E+99x."));
+        let info = TimeLoad::validate_csv("./test/legacy_format.csv").unwrap();
+        assert!(info.datetime_format == Some("%m/%d/%Y %H:%M"));
+        let dir = std::env::temp_dir();
+        let cases = [
+            ("load_lpp_test_validate_empty.csv", "", None),
+            ("load_lpp_test_validate_svg.csv", "<?xml version=\"1.0\"?>\n<svg>\n", Some(1)),
+            ("load_lpp_test_validate_rows.csv", "datetime,load_kg\n", None),
+            (
+                "load_lpp_test_validate_load.csv",
+                "datetime,load_kg\n2021-10-13T23:00:00-08:00,13000\n2021-10-13T23:01:00-08:00,x\n",
+                Some(3),
+            ),
+            (
+                "load_lpp_test_validate_code.csv",
+                "datetime,load_kg\n2021-10-13T23:00:00-08:00,Error\n",
+                Some(2),
+            ),
+        ];
+        for (name, content, line) in cases.iter() {
+            let fin = dir.join(name);
+            std::fs::write(&fin, content).unwrap();
+            let e = TimeLoad::validate_csv(&fin).unwrap_err();
+            assert!(e.line == *line, "{}", e);
+        }
+    }
+
+    #[test]
+    // The statistics skip the NANs, the sampling interval is the smallest step
+    fn test_stats() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let minutes = [0, 2, 4, 5, 7, 9];
+        let loads = [1., 2., f64::NAN, 3., 4., 10.];
+        let tl = TimeLoad::from_pairs(
+            (0..6).map(|i| (offset.timestamp_opt(60 * minutes[i], 0).unwrap(), loads[i])),
+        );
+        let stats = tl.stats();
+        assert!((stats.count == 6) & (stats.finite_count == 5) & (stats.nan_count == 1));
+        assert!((stats.min == 1.) & (stats.max == 10.));
+        assert!((stats.mean == 4.) & (stats.median == 3.));
+        assert!(compare_f64_approx(stats.std, 12.5f64.sqrt(), 1e-9));
+        assert!((stats.q25 == 2.) & (stats.q75 == 4.) & (stats.iqr == 2.));
+        assert!(stats.time_span == chrono::Duration::minutes(9));
+        assert!(stats.sampling_interval == Some(chrono::Duration::minutes(1)));
+        let single = TimeLoad::from_pairs([(offset.timestamp_opt(0, 0).unwrap(), f64::NAN)]);
+        let stats = single.stats();
+        assert!((stats.finite_count == 0) & stats.mean.is_nan() & stats.iqr.is_nan());
+        assert!(stats.sampling_interval.is_none());
+    }
+
+    #[test]
+    // LTTB keeps the peaks, the ends, and the NAN gaps with a bounded number of points
+    fn test_downsample_for_plot() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let mut loads: Vec<f64> = (0..1000).map(|i| 13000. + (i % 10) as f64).collect();
+        loads[300] = 14000.;
+        loads[700] = 12000.;
+        for l in loads[500..520].iter_mut() {
+            *l = f64::NAN;
+        }
+        let tl = TimeLoad::from_pairs(
+            (0..1000).map(|i| (offset.timestamp_opt(60 * i, 0).unwrap(), loads[i as usize])),
+        );
+        let dtl = tl.downsample_for_plot(100);
+        assert!(dtl.len() <= 100);
+        assert!(dtl.time.windows(2).all(|w| w[0] < w[1]));
+        assert!((dtl.time[0] == tl.time[0]) & (dtl.time[dtl.len() - 1] == tl.time[999]));
+        assert!(dtl.load.contains(&14000.) & dtl.load.contains(&12000.));
+        let nans: Vec<usize> = (0..dtl.len()).filter(|i| dtl.load[*i].is_nan()).collect();
+        assert!(nans.len() == 1);
+        assert!(dtl.time[nans[0]] == tl.time[500]);
+        assert!(dtl.time[nans[0] - 1] == tl.time[499]);
+        assert!(dtl.time[nans[0] + 1] == tl.time[520]);
+        assert!(tl.downsample_for_plot(1000).load.len() == 1000);
+        assert!(lttb_indices(&[0., 1., 2., 3.], &[0., 5., 0., 0.], 3) == vec![0, 1, 3]);
+    }
+
+    #[test]
+    // With many gaps, the separators and the runs still fit in max_points
+    fn test_downsample_for_plot_many_gaps() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        // runs of 3 finite loads separated by a single NAN, plus a long run at the end
+        let loads: Vec<f64> = (0..1000)
+            .map(|i| if (i < 800) & (i % 4 == 3) { f64::NAN } else { 13000. + (i % 7) as f64 })
+            .collect();
+        let tl = TimeLoad::from_pairs(
+            (0..1000).map(|i| (offset.timestamp_opt(60 * i, 0).unwrap(), loads[i as usize])),
+        );
+        for max_points in [0, 1, 2, 10, 100, 500, 999] {
+            let dtl = tl.downsample_for_plot(max_points);
+            assert!(dtl.len() <= max_points);
+            assert!(dtl.time.windows(2).all(|w| w[0] < w[1]));
+            assert!(dtl.load.windows(2).all(|w| !(w[0].is_nan() & w[1].is_nan())));
+        }
+        // the long run is the last to be dropped
+        let dtl = tl.downsample_for_plot(4);
+        assert!(dtl.len() == 4);
+        assert!(dtl.load.iter().all(|l| l.is_finite()));
+        assert!((dtl.time[0] == tl.time[800]) & (dtl.time[3] == tl.time[999]));
+    }
+
+    #[test]
+    // The x tick format of the plots follows the time span
+    fn test_time_span_xfmt() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let pairs = |minutes: &[i64]| {
+            TimeLoad::from_pairs(
+                minutes
+                    .iter()
+                    .map(|m| (offset.timestamp_opt(60 * m, 0).unwrap(), 13000.)),
+            )
+        };
+        assert!(pairs(&[]).time_span() == chrono::Duration::zero());
+        assert!(pairs(&[5]).time_span() == chrono::Duration::zero());
+        let day = pairs(&[0, 60, 600]);
+        assert!(day.time_span() == chrono::Duration::minutes(600));
+        assert!(suitable_xfmt(day.time_span()) == "%d %H:%M");
+        let years = pairs(&[0, 2 * 365 * 24 * 60]);
+        assert!(suitable_xfmt(years.time_span()) == "%y-%m-%d");
+    }
+
+    #[test]
+    // The y range of the plots is padded around the finite loads
+    fn test_plot_y_range() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let loads = [13000., f64::NAN, 13200., 13100.];
+        let tl = TimeLoad::from_pairs(
+            (0..4).map(|i| (offset.timestamp_opt(60 * i, 0).unwrap(), loads[i as usize])),
+        );
+        assert!(tl.plot_y_range(PLOT_Y_PAD_PCT) == Some((12990., 13210.)));
+        assert!(tl.plot_y_range(0.) == Some((13000., 13200.)));
+        let flat = TimeLoad::from_pairs([(offset.timestamp_opt(0, 0).unwrap(), 13000.)]);
+        assert!(flat.plot_y_range(10.) == Some((11700., 14300.)));
+        let empty = TimeLoad::from_pairs([(offset.timestamp_opt(0, 0).unwrap(), f64::NAN)]);
+        assert!(empty.plot_y_range(PLOT_Y_PAD_PCT).is_none());
+    }
+
+    #[test]
+    // Skip the NANs and infinities, return None without finite values
+    fn test_min_and_max_finite() {
+        let v = [f64::NAN, 3., -1., f64::INFINITY, 7., -1., f64::NAN, 7.];
+        assert!(min_and_max_finite(&v) == Some(((2, -1.), (4, 7.))));
+        assert!(min_and_max_finite(&[2.]) == Some(((0, 2.), (0, 2.))));
+        assert!(min_and_max_finite(&[f64::NAN, f64::NAN]).is_none());
+        assert!(min_and_max_finite(&[]).is_none());
+    }
+
+    #[test]
+    // With a cadence change, the time-weighted mean is not biased toward the dense samples
+    fn test_to_interval_time_weighted() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let start = offset.with_ymd_and_hms(2021, 10, 13, 23, 0, 0).unwrap();
+        // 0 every 10 minutes in the first half hour, then 60 every minute
+        let minutes = [0, 10, 20, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45];
+        let tl = TimeLoad::from_pairs(minutes.iter().map(|m| {
+            let load = if *m < 30 { 0. } else { 60. };
+            (start + chrono::Duration::minutes(*m), load)
+        }));
+        let nominal = chrono::Duration::minutes(10);
+        let weights = tl.time_weights(nominal);
+        assert!((weights[0] == 300.) & (weights[1] == 600.) & (weights[3] == 330.));
+        assert!(weights.iter().sum::<f64>() == 45. * 60.);
+        // with the 1 minute step, the 10 minute gaps are mostly missing data
+        let weights = tl.time_weights(chrono::Duration::minutes(1));
+        assert!((weights[0] == 30.) & (weights[1] == 60.) & (weights[3] == 60.));
+        let step = chrono::Duration::hours(2);
+        let nan = NanPolicy::Ignore { min_valid: 1 };
+        let plain = tl.to_interval(step, nan).unwrap();
+        let weighted = tl.to_interval_time_weighted(step, Some(nominal), nan).unwrap();
+        assert!(compare_f64_approx(plain.load[0], 60. * 16. / 19., 1e-9));
+        assert!(compare_f64_approx(weighted.load[0], 60. * 20. / 45., 1e-9));
+        assert!(NanPolicy::Propagate.weighted_mean(&[1., f64::NAN], &[1., 1.]).is_nan());
+        assert!(nan.weighted_mean(&[1., f64::NAN, 3.], &[1., 5., 3.]) == 2.5);
+        // a single load is weighted by the nominal step, thus kept
+        let single = TimeLoad::from_pairs([(start, 5.)]);
+        assert!(single.time_weights(chrono::Duration::minutes(1)) == [60.]);
+        let one_minute = Some(chrono::Duration::minutes(1));
+        let weighted = single.to_interval_time_weighted(step, one_minute, nan).unwrap();
+        assert!(weighted.load == [5.]);
+    }
+
+    #[test]
+    // Downsample to 15 minutes with the different aggregators, NANs are skipped
+    fn test_downsample_aggregators() {
+        let tl = TimeLoad::from_csv(String::from("./test/short_for_hourly.csv")).unwrap();
+        let step = chrono::Duration::minutes(15);
+        let expected = [
+            (Aggregator::Mean, [1.3f64, 2.0f64]),
+            (Aggregator::Median, [1.0f64, 2.0f64]),
+            (Aggregator::Min, [1.0f64, 2.0f64]),
+            (Aggregator::Max, [2.0f64, 2.0f64]),
+            (Aggregator::Range, [1.0f64, 0.0f64]),
+            (Aggregator::Sum, [13.0f64, 4.0f64]),
+            (Aggregator::First, [1.0f64, 2.0f64]),
+            (Aggregator::Last, [2.0f64, 2.0f64]),
+        ];
+        for (agg, loads) in expected.iter() {
+            let dtl = tl.downsample(step, *agg).expect("empty data set");
+            assert!(compare_vecf64_approx(&dtl.load, loads), "{:?}", agg);
+        }
+        assert!("range".parse::<Aggregator>() == Ok(Aggregator::Range));
+        assert!(Aggregator::Mean.aggregate(&vec![]).is_nan());
+    }
+
+    #[test]
+    // Read from an in-memory csv with the exact capacity, no reallocation needed
+    fn test_from_reader_with_capacity() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1.0\n\
+            2021-10-13T23:01:00-08:00,2.0\n";
+        let tl = TimeLoad::from_reader_with_capacity(csv.as_bytes(), 2usize).unwrap();
+        assert!(tl.time.capacity() == 2usize);
+        assert!(compare_vecf64_exact(&tl.load, &[1.0, 2.0]));
+    }
+
+    #[test]
+    // The truncated last line is dropped when reading live, while from_csv gives a NAN for it
+    fn test_from_csv_live_truncated_line() {
+        let tl = TimeLoad::from_csv("./test/live_truncated.csv").unwrap();
+        assert!(compare_vecf64_exact(&tl.load, &[13000.5, 13001.5, f64::NAN]));
+        let tl_live = TimeLoad::from_csv_live("./test/live_truncated.csv").unwrap();
+        assert!(compare_vecf64_exact(&tl_live.load, &[13000.5, 13001.5]));
+        assert!(tl_live.time.len() == 2usize);
+    }
+
+    #[test]
+    // A missing file or datetime field gives an error, a missing load field gives a NAN
+    fn test_from_csv_errors() {
+        assert!(TimeLoad::from_csv("./test/does_not_exist.csv").is_err());
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        assert!(compare_vecf64_exact(&tl.load, &[f64::NAN]));
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1.0\n\
+            ,2.0\n";
+        let e = TimeLoad::from_reader(csv.as_bytes()).unwrap_err();
+        assert!(e.to_string() == "Missing datetime column at line 3");
+    }
+
+    #[test]
+    // Select the columns of a csv with a non-standard header, by index or by name
+    fn test_from_csv_columns() {
+        let tl = TimeLoad::from_csv_with_columns("./test/historian.csv", 0, 2).unwrap();
+        assert!(compare_vecf64_exact(&tl.load, &[13000.5, 13001.5, f64::NAN]));
+        let tl_header =
+            TimeLoad::from_csv_by_header("./test/historian.csv", "timestamp", "value").unwrap();
+        assert!(tl_header.time == tl.time);
+        assert!(compare_vecf64_exact(&tl_header.load, &tl.load));
+        let e = TimeLoad::from_csv_by_header("./test/historian.csv", "timestamp", "load_kg");
+        assert!(e.unwrap_err().to_string() == "Missing load_kg column at line 1");
+    }
+
+    #[test]
+    // All the malformed bad datetimes are reported together, with their line number
+    fn test_read_bad_datetimes_malformed() {
+        let lines = match read_bad_datetimes("./test/bad_datetimes_malformed.csv") {
+            Err(BadDatetimeParseError::Lines(lines)) => lines,
+            other => panic!("expected the malformed lines, got {:?}", other),
+        };
+        assert!(lines.len() == 2usize);
+        assert!(lines[0] == (2usize, String::from("not a datetime")));
+        assert!(lines[1].0 == 4usize);
+        // a missing file is an error too, not a panic
+        let missing = read_bad_datetimes("./test/no_bad_datetimes.csv");
+        assert!(matches!(missing, Err(BadDatetimeParseError::Io(_))));
+    }
+
+    #[test]
+    // Comments and blank lines are skipped, the datetimes are trimmed
+    fn test_read_bad_datetimes_comments() {
+        let bad = read_bad_datetimes("./test/bad_datetimes_comments.csv").unwrap();
+        let correct_bad = vec![
+            DateTime::parse_from_rfc3339("2021-01-01T01:02:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-01-01T01:07:00-08:00").unwrap(),
+        ];
+        assert!(bad == correct_bad);
+    }
+
+    #[test]
+    // The bad intervals set to NAN all the loads between start and end, both included
+    fn test_replace_bad_interval_with_nan() {
+        let mut tl = TimeLoad::from_csv("./test/short_for_hourly.csv").unwrap();
+        let bad_intervals = read_bad_intervals("./test/bad_intervals.csv").unwrap();
+        assert!(bad_intervals.len() == 1usize);
+        for (start, end) in bad_intervals.into_iter() {
+            tl.replace_bad_interval_with_nan(start, end);
+        }
+        assert!(compare_vecf64_exact(&tl.load[..5], &[1., f64::NAN, f64::NAN, f64::NAN, 1.]));
+        let missing = read_bad_intervals("./test/no_bad_intervals.csv");
+        assert!(matches!(missing, Err(BadDatetimeParseError::Io(_))));
+    }
+
+    #[test]
+    // The loads with a bad quality flag become NAN, lines without the flag are good
+    fn test_from_csv_with_quality() {
+        let tl = TimeLoad::from_csv_with_quality("./test/quality.csv", &["BAD"]).unwrap();
+        assert!(compare_vecf64_exact(&tl.load, &[13000.5, 13001.5, f64::NAN, 13003.5]));
+        let bad_flags = ["SUSPECT", "BAD"];
+        let tl = TimeLoad::from_csv_with_quality("./test/quality.csv", &bad_flags).unwrap();
+        assert!(compare_vecf64_exact(&tl.load, &[13000.5, f64::NAN, f64::NAN, 13003.5]));
+        assert!(tl.time.len() == 4usize);
+    }
+
+    #[test]
+    // Write with a custom header and limited decimals, NANs are kept
+    fn test_to_csv_with_header() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,13000.123456\n\
+            2021-10-13T23:01:00-08:00,\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let fout = std::env::temp_dir().join("load_lpp_test_to_csv_with_header.csv");
+        tl.to_csv_with_header(&fout, "timestamp,load_lb", Some(2));
+        let written = std::fs::read_to_string(&fout).unwrap();
+        let expected = "timestamp,load_lb\n\
+            2021-10-13T23:00:00-08:00,13000.12\n\
+            2021-10-13T23:01:00-08:00,NaN\n";
+        assert!(written == expected);
+    }
+
+    #[test]
+    // With the given step, a double reading does not shrink the step and is dropped
+    fn test_fill_missing_with_nan_step() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1\n\
+            2021-10-13T23:00:10-08:00,9\n\
+            2021-10-13T23:02:00-08:00,2\n\
+            2021-10-13T23:08:00-08:00,5\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let ftl = tl.fill_missing_with_nan_step(chrono::Duration::minutes(2));
+        ftl.is_ordered_and_continuous();
+        assert!(ftl.time.len() == 5);
+        assert!(ftl.time[4] == tl.time[3]);
+        let expected = vec![1., 2., f64::NAN, f64::NAN, 5.];
+        assert!(compare_vecf64_exact(&ftl.load, &expected));
+    }
+
+    #[test]
+    // An off-cadence first sample is snapped to the grid, while an off-cadence duplicate
+    // of a time step is dropped in favour of the closer sample
+    fn test_fill_missing_with_nan_step_off_cadence() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:07-08:00,1\n\
+            2021-10-13T23:01:00-08:00,2\n\
+            2021-10-13T23:02:00-08:00,3\n\
+            2021-10-13T23:02:20-08:00,9\n\
+            2021-10-13T23:04:00-08:00,5\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let ftl = tl.fill_missing_with_nan_step(chrono::Duration::minutes(1));
+        ftl.is_ordered_and_continuous();
+        assert!(ftl.time[0] == DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap());
+        assert!(ftl.time[4] == tl.time[4]);
+        assert!(compare_vecf64_exact(&ftl.load, &[1., 2., 3., f64::NAN, 5.]));
+    }
+
+    #[test]
+    // The gaps are reported with the number of missing samples, with and without the step
+    fn test_find_gaps() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1\n\
+            2021-10-13T23:01:00-08:00,2\n\
+            2021-10-13T23:04:00-08:00,3\n\
+            2021-10-13T23:05:00-08:00,4\n\
+            2021-10-13T23:07:00-08:00,5\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let gaps = tl.find_gaps(None);
+        let missing: Vec<usize> = gaps.iter().map(|g| g.2).collect();
+        assert!(missing == vec![2, 1]);
+        assert!((gaps[0].0 == tl.time[1]) & (gaps[0].1 == tl.time[2]));
+        assert!(tl.find_gaps(None).len() == tl.fill_missing_with_nan().find_gaps(None).len() + 2);
+        let gaps = tl.find_gaps(Some(chrono::Duration::minutes(2)));
+        assert!((gaps.len() == 1) & (gaps[0].2 == 1));
+        // the duplicate datetimes are not a zero step
+        let mut dtl = tl.clone();
+        dtl.time.insert(1, tl.time[0]);
+        dtl.load.insert(1, 1.);
+        assert!(dtl.find_gaps(None).iter().map(|g| g.2).collect::<Vec<usize>>() == vec![2, 1]);
+        let same = TimeLoad::from_pairs(vec![(tl.time[0], 1.), (tl.time[0], 2.)]);
+        assert!(same.find_gaps(None).is_empty());
+        assert!(TimeLoad::new(0).find_gaps(None).is_empty());
+    }
+
+    #[test]
+    // Merge orders the datetimes and prefers finite values for the duplicates
+    fn test_merge() {
+        let csv_a = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1\n\
+            2021-10-13T23:02:00-08:00,\n\
+            2021-10-13T23:03:00-08:00,3\n";
+        let csv_b = "datetime,load_kg\n\
+            2021-10-13T23:01:00-08:00,2\n\
+            2021-10-13T23:02:00-08:00,20\n\
+            2021-10-13T23:03:00-08:00,30\n";
+        let a = TimeLoad::from_reader(csv_a.as_bytes()).unwrap();
+        let b = TimeLoad::from_reader(csv_b.as_bytes()).unwrap();
+        let merged = a.merge(&b);
+        merged.is_ordered();
+        assert!(merged.time.len() == 4);
+        assert!(compare_vecf64_exact(&merged.load, &[1., 2., 20., 3.]));
+    }
+
+    #[test]
+    // Build from pairs, iterator, and push, keeping time and load in lockstep
+    fn test_from_pairs_push() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let pairs: Vec<(DateTime<FixedOffset>, f64)> =
+            (0..3).map(|i| (t0 + chrono::Duration::minutes(i), i as f64)).collect();
+        let mut tl = TimeLoad::from_pairs(pairs.clone());
+        let itl: TimeLoad = pairs.into_iter().collect();
+        assert!(itl.time == tl.time);
+        assert!(compare_vecf64_exact(&itl.load, &tl.load));
+        assert!(tl.len() == 3);
+        tl.push(t0 + chrono::Duration::minutes(3), f64::NAN);
+        assert!(tl.len() == 4);
+        tl.assert_consistent();
+        assert!(TimeLoad::new(10).is_empty());
     }
 
     #[test]
-    // Get the reading datetime with the correct offset
-    fn test_get_current_datetime_offset() {
-        let dtnow: DateTime<Local> = Local::now();
-        println!(
-            "local time is {}",
-            dtnow.to_rfc3339_opts(SecondsFormat::Secs, false)
+    // The duplicates are collapsed according to the policy
+    fn test_dedup_timestamps() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1\n\
+            2021-10-13T23:01:00-08:00,2\n\
+            2021-10-13T23:01:00-08:00,4\n\
+            2021-10-13T23:02:00-08:00,5\n\
+            2021-10-13T23:02:00-08:00,\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let mut first = tl.clone();
+        assert!(first.dedup_timestamps(DupPolicy::KeepFirst) == 2);
+        first.is_ordered_and_continuous();
+        assert!(compare_vecf64_exact(&first.load, &[1., 2., 5.]));
+        let mut last = tl.clone();
+        last.dedup_timestamps(DupPolicy::KeepLast);
+        assert!(compare_vecf64_exact(&last.load, &[1., 4., f64::NAN]));
+        let mut average = tl.clone();
+        average.dedup_timestamps(DupPolicy::Average);
+        assert!(compare_vecf64_exact(&average.load, &[1., 3., f64::NAN]));
+        assert!(average.time == first.time);
+    }
+
+    #[test]
+    // Sorting a shuffled series keeps each load with its datetime
+    fn test_sort_by_time() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let order = [3, 0, 4, 1, 2, 6, 5];
+        let mut tl = TimeLoad::from_pairs(
+            order
+                .iter()
+                .map(|i| (t0 + chrono::Duration::minutes(*i), 13000. + *i as f64)),
         );
-        let local_offset = dtnow.offset();
-        println!("local offet is {}", local_offset);
+        tl.load[2] = f64::NAN;
+        tl.sort_by_time();
+        tl.is_ordered_and_continuous();
+        let expected = vec![13000., 13001., 13002., 13003., f64::NAN, 13005., 13006.];
+        assert!(compare_vecf64_exact(&tl.load, &expected));
     }
 
     #[test]
-    // Assert that a homogenous load time series gives no anomalies
-    fn test_find_anomaly_homogeneous() {
-        let a = [5.0f64; 15];
-        let expected: Vec<f64> = Vec::new();
-        let (_, anomalies_load) = find_anomalies(&a, 7usize, 6usize, 5.0f64);
-        assert!(anomalies_load == expected);
+    // The errors report the first offending index and its datetimes
+    fn test_try_is_ordered_continuous() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,1\n\
+            2021-10-13T23:01:00-08:00,2\n\
+            2021-10-13T23:03:00-08:00,3\n\
+            2021-10-13T23:02:00-08:00,4\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let err = tl.try_is_ordered().unwrap_err();
+        assert!(err.index == 3);
+        assert!((err.previous == tl.time[2]) & (err.next == tl.time[3]));
+        let err = tl.try_is_continuous().unwrap_err();
+        assert!(err.index == 2);
+        assert!(err.expected_step == chrono::Duration::minutes(1));
+        let ftl = TimeLoad::from_reader(csv.as_bytes())
+            .unwrap()
+            .fill_missing_with_nan_step(chrono::Duration::minutes(1));
+        assert!(ftl.try_is_ordered().is_ok());
+        assert!(ftl.try_is_continuous().is_ok());
     }
 
     #[test]
-    // Assert that a linear load time series with small increment gives no anomalies
-    fn test_find_anomaly_linear() {
-        let v: Vec<f64> = (1..15).map(|n| n as f64).collect();
-        let expected: Vec<f64> = Vec::new();
-        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
-        assert!(anomalies_load == expected);
+    // After the conversion all the datetimes share the offset, the instants do not change
+    fn test_to_timezone() {
+        let csv = "datetime,load_kg\n\
+            2021-11-07T01:30:00-07:00,1\n\
+            2021-11-07T01:00:00-08:00,2\n";
+        let mut tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let original = tl.time.clone();
+        let result = std::panic::catch_unwind(|| tl.assert_fixed_offset());
+        assert!(result.is_err());
+        tl.to_timezone(FixedOffset::east_opt(-8 * 60 * 60).unwrap());
+        tl.assert_fixed_offset();
+        assert!(tl.time == original);
+        assert!(tl.time[0].to_rfc3339() == "2021-11-07T00:30:00-08:00");
     }
 
     #[test]
-    // Assert that a NANs are correctly handled while finding anomalies
-    fn test_find_anomaly_nans() {
-        let mut v: Vec<f64> = (1..15).map(|n| n as f64).collect();
-        v.iter_mut().enumerate().for_each(|(i, e)| {
-            if i < 6usize {
-                *e = f64::NAN
-            }
-        });
-        let expected: Vec<f64> = Vec::new();
-        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
-        assert!(anomalies_load == expected);
+    // Interpolate at the target datetimes, NAN outside and in the gaps longer than max_gap
+    fn test_resample_to() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,10\n\
+            2021-10-13T23:10:00-08:00,20\n\
+            2021-10-13T23:20:00-08:00,\n\
+            2021-10-13T23:30:00-08:00,40\n\
+            2021-10-14T01:00:00-08:00,50\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let targets: Vec<DateTime<FixedOffset>> = [
+            "2021-10-13T22:55:00-08:00",
+            "2021-10-13T23:05:00-08:00",
+            "2021-10-13T23:10:00-08:00",
+            "2021-10-13T23:25:00-08:00",
+            "2021-10-14T00:00:00-08:00",
+        ]
+        .iter()
+        .map(|t| DateTime::parse_from_rfc3339(t).unwrap())
+        .collect();
+        let rtl = tl.resample_to(&targets, chrono::Duration::minutes(30));
+        assert!(rtl.time == targets);
+        let expected = vec![f64::NAN, 15., 20., 35., f64::NAN];
+        assert!(compare_vecf64_exact(&rtl.load, &expected));
     }
 
     #[test]
-    // Assert that the anomalies are correctly identified by adding a big discontinuity
-    fn test_find_anomaly_discontinuity() {
-        let mut v: Vec<f64> = (1..15).map(|n| n as f64).collect();
-        v.iter_mut().enumerate().for_each(|(i, e)| {
-            if i < 8usize {
-                *e = 20.
-            }
-        });
-        let (_, anomalies_load) = find_anomalies(&v, 7usize, 6usize, 5.0f64);
-        let expected: Vec<f64> = vec![20.0, 20.0, 20.0, 20.0, 9.0, 10.0, 11.0, 12.0, 13.0];
-        assert!(anomalies_load == expected);
+    // A time without its load is reported by validate
+    fn test_validate() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::from_pairs(vec![(t0, 1.)]);
+        assert!(tl.validate().is_ok());
+        tl.time.push(t0 + chrono::Duration::minutes(1));
+        let err = tl.validate().unwrap_err();
+        assert!((err.time_len == 2) & (err.load_len == 1));
     }
 
     #[test]
-    // Deduplicate removes consecutive repeated elements,
-    // thus if the input is sorted dedup returns no duplicates
-    fn test_deduplicate_load_series() {
-        let mut v_all = vec![1., 2., 2., 2., 3., 4., 4., 5., 6., 6.];
-        let v_unique = vec![1., 2., 3., 4., 5., 6.];
-        let v_duplicates = vec![2., 2., 4., 6.];
+    // The json round trip keeps the datetimes and the NANs, written as null
+    fn test_json() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,13000.5\n\
+            2021-10-13T23:01:00-08:00,\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let fout = std::env::temp_dir().join("load_lpp_test_json.json");
+        tl.to_json(&fout).unwrap();
+        let written = std::fs::read_to_string(&fout).unwrap();
+        let expected = "{\"time\":[\"2021-10-13T23:00:00-08:00\",\"2021-10-13T23:01:00-08:00\"],\
+            \"load\":[13000.5,null]}";
+        assert!(written == expected);
+        let jtl = TimeLoad::from_json(&fout).unwrap();
+        assert!(jtl.time == tl.time);
+        assert!(compare_vecf64_exact(&jtl.load, &tl.load));
+    }
 
-        v_all.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let (dedup, duplicates) = v_all.partition_dedup_by(|a, b| a == b);
+    #[test]
+    #[cfg(feature = "parquet")]
+    // The parquet round trip keeps the datetimes, with their offset, and the NANs
+    fn test_parquet() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,13000.5\n\
+            2021-10-13T23:01:00-08:00,\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let fout = std::env::temp_dir().join("load_lpp_test_parquet.parquet");
+        tl.to_parquet(&fout).unwrap();
+        let ptl = TimeLoad::from_parquet(&fout).unwrap();
+        assert!(ptl.time == tl.time);
+        assert!(ptl.time[0].offset() == tl.time[0].offset());
+        assert!(compare_vecf64_exact(&ptl.load, &tl.load));
+    }
 
-        let mut duplicates = duplicates.to_owned();
-        duplicates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    #[test]
+    // Append writes the header only for a new file and does not duplicate it afterwards
+    fn test_append_to_csv() {
+        let csv = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,13000\n\
+            2021-10-13T23:01:00-08:00,13001\n";
+        let tl = TimeLoad::from_reader(csv.as_bytes()).unwrap();
+        let fout = std::env::temp_dir().join("load_lpp_test_append_to_csv.csv");
+        std::fs::write(&fout, "").unwrap();
+        tl.append_to_csv(&fout);
+        tl.append_to_csv(&fout);
+        let written = std::fs::read_to_string(&fout).unwrap();
+        let expected = "datetime,load_kg\n\
+            2021-10-13T23:00:00-08:00,13000\n\
+            2021-10-13T23:01:00-08:00,13001\n\
+            2021-10-13T23:00:00-08:00,13000\n\
+            2021-10-13T23:01:00-08:00,13001\n";
+        assert!(written == expected);
+        std::fs::write(&fout, "datetime,load_kg").unwrap();
+        tl.append_to_csv(&fout);
+        let written = std::fs::read_to_string(&fout).unwrap();
+        assert!(written == csv);
+    }
 
-        // println!("test:\ndedup {:?} == {:?}?\nduplicates {:?} == {:?}?", dedup, v_unique, duplicates, v_duplicates);
-        assert!(v_unique == dedup);
-        assert!(v_duplicates == duplicates);
+    #[test]
+    // Parse a legacy datetime format without offset, taking the assumed offset
+    fn test_from_csv_with_format() {
+        let offset = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let tl =
+            TimeLoad::from_csv_with_format("./test/legacy_format.csv", "%m/%d/%Y %H:%M", offset)
+                .unwrap();
+        let correct_times = vec![
+            DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-13T23:01:00-08:00").unwrap(),
+        ];
+        assert!(tl.time == correct_times);
+        assert!(compare_vecf64_exact(&tl.load, &[13000.5, 13001.5]));
     }
 
     #[test]
-    fn test_discharge_by_index() {
-        let vall: Vec<f64> = (1..20).map(|n| n as f64).collect();
-        let indices: Vec<usize> = vec![2, 3, 7, 12, 13, 18];
-        let mut expected: Vec<f64> = (1..20).map(|n| n as f64).collect();
-        for i in indices.iter().rev() {
-            expected.remove(*i);
+    // Downsampling to a number of points keeps the spikes and does not exceed the points
+    fn test_downsample_to() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T00:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(1000);
+        for m in 0..1000 {
+            tl.time.push(t0 + chrono::Duration::minutes(m));
+            tl.load.push(13000.);
         }
-        let vout = discharge_by_index(&vall, &indices);
-        println!("vout\n{:?}\nexpected\n{:?}", vout, expected);
-        assert!(compare_vecf64_approx(&vout, &expected));
+        tl.load[123] = 15000.;
+        tl.load[456] = 11000.;
+        let dtl = tl.downsample_to(50usize);
+        assert!(dtl.load.len() <= 50usize);
+        assert!(dtl.load.contains(&15000.) & dtl.load.contains(&11000.));
+        dtl.is_ordered();
+        assert!(tl.downsample_to(1usize).len() == 1usize);
+        assert!(tl.downsample_to(0usize).is_empty());
     }
 
     #[test]
-    fn test_setnan_by_index() {
-        let mut vall: Vec<f64> = (1..20).map(|n| n as f64).collect();
-        let indices: Vec<usize> = vec![2, 3, 7, 12, 13, 18];
-        let mut expected: Vec<f64> = (1..20).map(|n| n as f64).collect();
-        indices.iter().for_each(|i| expected[*i] = f64::NAN);
-        setnan_by_index(&mut vall, &indices);
-        assert!(compare_vecf64_approx(&vall, &expected));
+    // A daily bad interval is bridged by linear interpolation between its neighbors
+    fn test_replace_bad_time_interval_interpolate() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T00:55:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(15);
+        for m in 0..15 {
+            tl.time.push(t0 + chrono::Duration::minutes(m));
+            tl.load.push(if (m > 7) & (m < 10) { 99999. } else { m as f64 });
+        }
+        let time_init = NaiveTime::parse_from_str("01:02", "%H:%M").unwrap();
+        let time_stop = NaiveTime::parse_from_str("01:05", "%H:%M").unwrap();
+        let mut ntl = tl.clone();
+        ntl.replace_bad_time_interval(time_init, time_stop, BadIntervalMode::Nan);
+        assert!(ntl.load[8].is_nan() & ntl.load[9].is_nan());
+        tl.replace_bad_time_interval(time_init, time_stop, BadIntervalMode::LinearInterpolate);
+        let expected: Vec<f64> = (0..15).map(|m| m as f64).collect();
+        assert!(compare_vecf64_approx(&tl.load, &expected));
+        // a missing reading right before the interval is skipped for the closest finite load
+        let mut mtl = ntl.clone();
+        mtl.load[6] = f64::NAN;
+        mtl.replace_bad_time_interval(time_init, time_stop, BadIntervalMode::LinearInterpolate);
+        assert!(mtl.load[6].is_nan());
+        assert!(compare_vecf64_approx(&mtl.load[7..], &expected[7..]));
     }
 
     #[test]
-    // full processing test, including all the optional steps
-    fn test_all_steps() {
-        // define time zone for the test
-        let mut timezone: i32 = -8;
-        timezone *= 60 * 60;
-        let timezone_fixed_offset = FixedOffset::east_opt(timezone).unwrap();
+    // A daily interval spanning midnight masks the end of a day and the start of the next,
+    // the bounds are included
+    fn test_replace_bad_time_interval_midnight() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(12);
+        for m in 0..12 {
+            tl.time.push(t0 + chrono::Duration::minutes(15 * m));
+            tl.load.push(1.);
+        }
+        let time_init = NaiveTime::parse_from_str("23:30", "%H:%M").unwrap();
+        let time_stop = NaiveTime::parse_from_str("00:30", "%H:%M").unwrap();
+        tl.replace_bad_time_interval_with_nan(time_init, time_stop);
+        let masked: Vec<usize> = (0..12).filter(|i| tl.load[*i].is_nan()).collect();
+        assert!(masked == vec![2, 3, 4, 5, 6]);
+    }
 
-        // read the time series and adjust to the deifned time zone
-        let mut tl = TimeLoad::from_csv(String::from("./test/timeload_raw.csv"));
-        tl.time
-            .iter_mut()
-            .for_each(|t| *t = t.with_timezone(&timezone_fixed_offset));
-        println!("{}", tl);
+    #[test]
+    // Parse the DAD141 replies after the description prefix, flag short and invalid replies
+    fn test_parse_dad141_load() {
+        use crate::load_log_dad141::{parse_dad141_load, DAD141_PREFIX_LEN};
+        let prefix = "GN";
+        assert!(prefix.len() == DAD141_PREFIX_LEN);
+        assert!(parse_dad141_load(&format!("{}+013000.5", prefix)) == 13000.5);
+        assert!(parse_dad141_load(&format!("{}-000012.0", prefix)) == -12.0);
+        assert!(parse_dad141_load(&prefix[..DAD141_PREFIX_LEN - 1]) == ERROR_FLT_PARSE);
+        assert!(parse_dad141_load(&format!("{}+0130x0.5", prefix)) == ERROR_FLT_PARSE);
+    }
 
-        // make sure the time series is ordered before processing
-        tl.is_ordered();
+    #[test]
+    // Decode the captured DAD141 replies, including empty, non-UTF8, and short ones
+    fn test_decode_reading() {
+        use crate::load_log_dad141::decode_reading;
+        let codes = ErrorCodes::default();
+        let (w, raw) = decode_reading(b"GN+013000.5\r\n", None, &codes);
+        assert!((w == 13000.5) & (raw == "GN+013000.5"));
+        let (w, raw) = decode_reading(b"GA-000012.0\n", None, &codes);
+        assert!((w == -12.0) & (raw == "GA-000012.0"));
+        let (w, raw) = decode_reading(b"", None, &codes);
+        assert!((w == ERROR_FLT_NONE) & (raw == ERROR_STR_NONE));
+        let (w, raw) = decode_reading(b"\r\n", None, &codes);
+        assert!((w == ERROR_FLT_NONE) & (raw == ERROR_STR_NONE));
+        let (w, raw) = decode_reading(b"0x\xff\xfe", None, &codes);
+        assert!((w == ERROR_FLT_INVALID) & (raw == ERROR_STR_INVALID));
+        let (w, raw) = decode_reading(b"G\r\n", None, &codes);
+        assert!((w == ERROR_FLT_PARSE) & (raw == "G"));
+        let (w, _) = decode_reading(b"GN+013000.5\r\n", Some(2), &codes);
+        assert!(w == 13000.5);
+        let codes = ErrorCodes::from_base(9999990.);
+        let (w, raw) = decode_reading(b"", None, &codes);
+        assert!((w == 9999998.) & (raw == "E+9999998."));
+        let (w, _) = decode_reading(b"G\r\n", None, &codes);
+        assert!(w == 9999995.);
+    }
 
-        // make continuous by filling missing values with NANs, then assert continuity
-        let mut ctl = tl.fill_missing_with_nan();
-        ctl.is_ordered_and_continuous();
-        println!("{}", ctl);
+    #[test]
+    // The simulated replies are parsed as the DAD141 ones and stay around the baseline
+    fn test_simulate_reading() {
+        use crate::load_log_dad141::{parse_dad141_load, simulate_reading};
+        let dt = Local.with_ymd_and_hms(2021, 10, 13, 23, 0, 0).unwrap();
+        let reply = simulate_reading(dt, "GN");
+        assert!(reply.starts_with("GN+0"));
+        assert!(reply == simulate_reading(dt, "GN"));
+        for m in 0..100 {
+            let w = parse_dad141_load(&simulate_reading(dt + chrono::Duration::minutes(m), "GN"));
+            assert!((w > 12940.) & (w < 13060.));
+        }
+    }
 
-        // read bad datetimes and replace them with NANs
-        let bad = read_bad_datetimes("./test/bad_datetimes.csv");
-        ctl.replace_bad_datetimes_with_nan(bad);
-        println!("{}", ctl);
+    #[test]
+    // Each line is written through to the file, with and without fsync
+    fn test_write_csv_line() {
+        use crate::load_log_dad141::{prepare_csvfile, write_csv_line};
+        let fout = std::env::temp_dir().join("load_lpp_test_write_csv_line.csv");
+        let _ = std::fs::remove_file(&fout);
+        let mut csvfile = prepare_csvfile(fout.to_str().unwrap());
+        let line = "2021-10-13T23:00:00-08:00,13000,GN+013000.0\n";
+        write_csv_line(&mut csvfile, line, false).unwrap();
+        let line = "2021-10-13T23:01:00-08:00,13001,GN+013001.0\n";
+        write_csv_line(&mut csvfile, line, true).unwrap();
+        let written = std::fs::read_to_string(&fout).unwrap();
+        let expected = "datetime,load_kg,raw_reading\n\
+            2021-10-13T23:00:00-08:00,13000,GN+013000.0\n\
+            2021-10-13T23:01:00-08:00,13001,GN+013001.0\n";
+        assert!(written == expected);
+    }
 
-        // define a daily interval over which values should be ignored (maintenance, etc.),
-        // then these intervals to NAN
-        let time_init = NaiveTime::parse_from_str("01:02", "%H:%M").unwrap();
-        let time_stop = NaiveTime::parse_from_str("01:05", "%H:%M").unwrap();
-        ctl.replace_bad_time_interval_with_nan(time_init, time_stop);
-        println!("{}", ctl);
+    #[test]
+    // The reconnection delay doubles up to the cap, with at most 10% of jitter
+    fn test_reconnect_backoff() {
+        use crate::load_log_dad141::reconnect_backoff;
+        use std::time::Duration;
+        let base = Duration::from_secs(5);
+        let max = Duration::from_secs(300);
+        let delays: Vec<u64> = (0..8)
+            .map(|a| reconnect_backoff(a, base, max, 0.).as_secs())
+            .collect();
+        assert!(delays == vec![5, 10, 20, 40, 80, 160, 300, 300]);
+        assert!(reconnect_backoff(u32::MAX, base, max, 0.) == max);
+        let jittered = reconnect_backoff(6, base, max, 0.5);
+        assert!((jittered > max) & (jittered <= max.mul_f64(1.1)));
+    }
 
-        // replace errors with NANs, in this case all the values above 99995.
-        ctl.replace_errors_with_nan(99995.);
-        println!("{}", ctl);
+    #[test]
+    // The status file is replaced by the last reading
+    fn test_write_status_file() {
+        use crate::load_log_dad141::{write_status_file, LoggerStatus};
+        let fout = std::env::temp_dir().join("load_lpp_test_status.json");
+        let fout = fout.to_str().unwrap();
+        let mut status = LoggerStatus {
+            datetime: "2021-10-13T23:00:00-08:00",
+            load_kg: 13000.5,
+            connected: true,
+        };
+        write_status_file(fout, &status).unwrap();
+        status.datetime = "2021-10-13T23:01:00-08:00";
+        write_status_file(fout, &status).unwrap();
+        let written = std::fs::read_to_string(fout).unwrap();
+        let expected =
+            r#"{"datetime":"2021-10-13T23:01:00-08:00","load_kg":13000.5,"connected":true}"#;
+        assert!(written == expected);
+        assert!(!std::path::Path::new(&format!("{}.tmp", fout)).exists());
+    }
 
-        // keep only load values within a specific range, set the outliers to NAN
-        ctl.replace_outliers_with_nan(10000., 18000.);
-        println!("{}", ctl);
+    #[test]
+    // The default error codes are the constants, other bases move all of them
+    fn test_error_codes() {
+        let codes = ErrorCodes::default();
+        assert!(codes.general == ERROR_FLT_GENERAL);
+        assert!(codes.parse == ERROR_FLT_PARSE);
+        assert!(codes.to_str(codes.skipped) == ERROR_STR_SKIPPED);
+        assert!(codes.to_str(codes.none) == ERROR_STR_NONE);
+        assert!(codes.is_error_code(ERROR_FLT_INVALID));
+        assert!(!codes.is_error_code(999994.));
+        let codes = ErrorCodes::from_base(9999990.);
+        assert!(codes.is_error_code(9999999.));
+        assert!(!codes.is_error_code(ERROR_FLT_GENERAL));
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let loads = [150000., 9999998., 9999995.];
+        let mut tl = TimeLoad::from_pairs(
+            (0..3).map(|i| (offset.timestamp_opt(60 * i, 0).unwrap(), loads[i as usize])),
+        );
+        tl.replace_error_codes_with_nan(&codes);
+        assert!(tl.load[0] == 150000.);
+        assert!(tl.load[1].is_nan() & tl.load[2].is_nan());
+    }
 
-        // find anomalies and set to NAN
-        let (anomalies_indices, _) = find_anomalies(&ctl.load, 16usize, 8usize, 40.0f64);
-        println!("indices of the anomalies:\n{:?}", anomalies_indices);
-        let mut atl = TimeLoad::new(anomalies_indices.len());
-        for i in anomalies_indices.iter() {
-            atl.time.push(ctl.time.get(*i).unwrap().clone());
-            atl.load.push(ctl.load.get(*i).unwrap().clone());
-        }
-        atl.to_csv("./test/timeload_anomalies.csv");
+    #[test]
+    // Count each kind of error code, ignoring valid loads and NAN
+    fn test_error_summary() {
+        let offset = FixedOffset::east_opt(0).unwrap();
+        let loads = [
+            13000.,
+            ERROR_FLT_SKIPPED,
+            ERROR_FLT_PARSE,
+            f64::NAN,
+            ERROR_FLT_SKIPPED,
+            ERROR_FLT_GENERAL,
+        ];
+        let tl = TimeLoad::from_pairs(
+            (0..6).map(|i| (offset.timestamp_opt(60 * i, 0).unwrap(), loads[i as usize])),
+        );
+        let summary = tl.error_summary();
+        assert!(summary.len() == 3);
+        assert!(summary[&ErrorKind::Skipped] == 2);
+        assert!(summary[&ErrorKind::Parse] == 1);
+        assert!(summary[&ErrorKind::General] == 1);
+        assert!(!summary.contains_key(&ErrorKind::None));
+        assert!(tl.error_summary_with_codes(&ErrorCodes::from_base(0.)).is_empty());
+    }
 
-        setnan_by_index(&mut ctl.load[..], &anomalies_indices);
+    #[test]
+    // The logging stops at the first limit reached, never without limits
+    fn test_logging_done() {
+        use crate::load_log_dad141::logging_done;
+        let dt = Local.with_ymd_and_hms(2021, 10, 13, 23, 0, 0).unwrap();
+        let end = Some(dt + chrono::Duration::minutes(10));
+        assert!(!logging_done(1000, None, dt, None));
+        assert!(!logging_done(2, Some(3), dt, None));
+        assert!(logging_done(3, Some(3), dt, None));
+        assert!(!logging_done(0, None, dt + chrono::Duration::minutes(8), end));
+        assert!(logging_done(0, None, dt + chrono::Duration::minutes(10), end));
+        assert!(logging_done(3, Some(3), dt, end));
+    }
 
-        // apply a weighted moving average to smooth the filtered time series
-        let mavg_window = make_window(3., 1., 5usize);
-        let smooth = mavg(&ctl.load[..], &mavg_window, 5 as usize, 80.);
-        println!("{:?}", smooth);
+    #[test]
+    // Parse with a longer prefix, given or auto-detected
+    fn test_parse_dad141_load_with_prefix() {
+        use crate::load_log_dad141::parse_dad141_load_with_prefix;
+        assert!(parse_dad141_load_with_prefix("GNx+013000.5", Some(3)) == 13000.5);
+        assert!(parse_dad141_load_with_prefix("GNx+013000.5", Some(2)) == ERROR_FLT_PARSE);
+        assert!(parse_dad141_load_with_prefix("GNx+013000.5", None) == 13000.5);
+        assert!(parse_dad141_load_with_prefix("GA-000012.0", None) == -12.0);
+        assert!(parse_dad141_load_with_prefix("GN 13000", None) == 13000.);
+        assert!(parse_dad141_load_with_prefix(ERROR_STR_NONE, None) == ERROR_FLT_NONE);
+        assert!(parse_dad141_load_with_prefix("GN", None) == ERROR_FLT_PARSE);
+    }
 
-        // in this case simply replace the original load series with the smooth one;
-        // if preferred keep both and compare
-        ctl.load = smooth;
+    #[test]
+    // Time and load vectors with matched lengths give a TimeLoad, mismatched an error
+    fn test_from_vecs() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let time: Vec<DateTime<FixedOffset>> =
+            (0..3).map(|m| t0 + chrono::Duration::minutes(m)).collect();
+        let tl = TimeLoad::from_vecs(time.clone(), vec![1., 2., 3.]).unwrap();
+        assert!(tl.time == time);
+        let err = TimeLoad::from_vecs(time, vec![1., 2.]).unwrap_err();
+        assert!((err.time_len == 3usize) & (err.load_len == 2usize));
+    }
 
-        // plot the filtered and smooth load series
-        ctl.plotly_plot_datetime("./test/timeload_processed.svg").unwrap();
+    #[test]
+    // Read a short mask file and set to NAN where the mask is false
+    fn test_apply_mask() {
+        let mask = read_mask("./test/mask.csv").unwrap();
+        assert!(mask == vec![true, true, false, true, false]);
+        assert!(read_mask("./test/no_mask.csv").is_err());
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(5);
+        for m in 0..5 {
+            tl.time.push(t0 + chrono::Duration::minutes(m));
+            tl.load.push(m as f64);
+        }
+        tl.apply_mask(&mask);
+        let expected = vec![0., 1., f64::NAN, 3., f64::NAN];
+        assert!(compare_vecf64_exact(&tl.load, &expected));
+    }
 
-        // save the filtered and smooth load series
-        ctl.to_csv("./test/timeload_processed.csv");
+    #[test]
+    // The longest of two runs of finite loads is found, None for all-NAN and empty series
+    fn test_longest_valid_run() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(10);
+        let loads = [f64::NAN, 1., 2., f64::NAN, 3., 4., 5., 6., f64::NAN, 7.];
+        for (m, l) in loads.iter().enumerate() {
+            tl.time.push(t0 + chrono::Duration::minutes(m as i64));
+            tl.load.push(*l);
+        }
+        assert!(tl.longest_valid_run() == Some((4usize, 7usize)));
+        tl.load.iter_mut().for_each(|l| *l = f64::NAN);
+        assert!(tl.longest_valid_run().is_none());
+        assert!(TimeLoad::new(0).longest_valid_run().is_none());
     }
 
     #[test]
-    fn test_to_hourly() {
-        let tl = TimeLoad::from_csv(String::from("./test/short_for_hourly.csv"));
-        let htl = &tl.to_hourly().expect("empty data set");
-        let correct_hourly_loads = vec![1.0f64, 1.5f64];
-        let correct_hourly_times = vec![
-            DateTime::parse_from_rfc3339("2021-10-13T23:00:00-08:00").unwrap(),
-            DateTime::parse_from_rfc3339("2021-10-14T00:00:00-08:00").unwrap(),
+    // A load increasing by 2 kg per hour gives a trend of 48 kg per day, NANs excluded
+    fn test_linear_trend() {
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T00:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(48);
+        for h in 0..48 {
+            tl.time.push(t0 + chrono::Duration::hours(h));
+            tl.load.push(1000. + 2. * h as f64);
+        }
+        tl.load[10] = f64::NAN;
+        let (slope, intercept) = tl.linear_trend();
+        assert!(compare_f64_approx(slope, 48., 1E-6));
+        assert!(compare_f64_approx(intercept, 1000., 1E-6));
+    }
+
+    #[test]
+    // Daily means with days starting at 07:00, the samples before 07:00 go to the previous day
+    fn test_to_daily_from_day_start() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T00:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(48);
+        for h in 0..48 {
+            tl.time.push(t0 + chrono::Duration::hours(h));
+            tl.load.push(if h < 7 { 1. } else if h < 31 { 2. } else { 3. });
+        }
+        let day_start = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+        let dtl = tl.to_daily_from(tz, day_start).expect("empty data set");
+        let correct_daily_times = vec![
+            DateTime::parse_from_rfc3339("2021-10-12T07:00:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-13T07:00:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-14T07:00:00-08:00").unwrap(),
         ];
-        assert! {compare_vecf64_approx(&htl.load, &correct_hourly_loads)};
-        assert! {htl.time == correct_hourly_times};
+        assert!(dtl.time == correct_daily_times);
+        assert!(compare_vecf64_exact(&dtl.load, &[1., 2., 3.]));
+    }
+
+    #[test]
+    // Daily means are bucketed by local calendar day, not by UTC day
+    fn test_to_daily() {
+        let tz = FixedOffset::east_opt(-8 * 60 * 60).unwrap();
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T00:00:00+00:00").unwrap();
+        let mut tl = TimeLoad::new(24);
+        for h in 0..24 {
+            tl.time.push(t0 + chrono::Duration::hours(h));
+            tl.load.push(if h < 8 { 1. } else if h < 12 { f64::NAN } else { 3. });
+        }
+        let dtl = tl.to_daily(tz).expect("empty data set");
+        let correct_daily_times = vec![
+            DateTime::parse_from_rfc3339("2021-10-12T00:00:00-08:00").unwrap(),
+            DateTime::parse_from_rfc3339("2021-10-13T00:00:00-08:00").unwrap(),
+        ];
+        assert!(dtl.time == correct_daily_times);
+        assert!(compare_vecf64_exact(&dtl.load, &[1., 3.]));
+        assert!(TimeLoad::new(0).to_daily(tz).is_err());
+    }
+
+    #[test]
+    // Assert that a flat start followed by an oscillating end gives a flatline and an anomaly,
+    // merged into one anomaly as they overlap, and that a single step is found on its own
+    fn test_events() {
+        assert!(index_runs(&[1, 2, 3, 7, 9, 10]) == vec![(1, 3), (7, 7), (9, 10)]);
+        let t0 = DateTime::parse_from_rfc3339("2021-10-13T00:00:00-08:00").unwrap();
+        let mut tl = TimeLoad::new(30);
+        for h in 0..30 {
+            tl.time.push(t0 + chrono::Duration::hours(h));
+            tl.load.push(if h < 15 { 10. } else { (h % 2) as f64 * 100. });
+        }
+        let config = EventConfig {
+            anomaly_width: 12,
+            anomaly_iqr: 5.,
+            step_width: 0,
+            flatline_min_len: 12,
+            ..Default::default()
+        };
+        let events = tl.events(&config);
+        assert!(events.len() == 1);
+        assert!(events[0].kind == EventKind::Anomaly);
+        assert!((events[0].start == tl.time[0]) & (events[0].end == tl.time[29]));
+        assert!(compare_f64_exact(events[0].magnitude, 100.));
+        // a flat baseline bumped once by 50, then flat again
+        let mut tl = TimeLoad::new(40);
+        for h in 0..40 {
+            tl.time.push(t0 + chrono::Duration::hours(h));
+            tl.load.push(if h < 20 { 10. } else { 60. } + (h % 3) as f64 * 0.1);
+        }
+        let config = EventConfig {
+            anomaly_width: 0,
+            step_width: 5,
+            min_step: 20.,
+            flatline_min_len: 0,
+            ..Default::default()
+        };
+        let events = tl.events(&config);
+        assert!(events.len() == 1);
+        assert!(events[0].kind == EventKind::Step);
+        assert!((events[0].start == tl.time[19]) & (events[0].end == tl.time[20]));
+        assert!((events[0].magnitude - 50.).abs() < 0.1);
     }
 
     #[test]
@@ -594,7 +4576,7 @@ mod tests {
         let timezone_fixed_offset = FixedOffset::east_opt(timezone).unwrap();
 
         // read the time series and adjust to the deifned time zone
-        let mut tl = TimeLoad::from_csv(String::from("./test/parallel_timeload_raw.csv"));
+        let mut tl = TimeLoad::from_csv(String::from("./test/parallel_timeload_raw.csv")).unwrap();
         tl.time
             .iter_mut()
             .for_each(|t| *t = t.with_timezone(&timezone_fixed_offset));
@@ -609,7 +4591,7 @@ mod tests {
         println!("{}", ctl);
 
         // read bad datetimes and replace them with NANs
-        let bad = read_bad_datetimes("./test/parallel_bad_datetimes.csv");
+        let bad = read_bad_datetimes("./test/parallel_bad_datetimes.csv").unwrap();
         ctl.replace_bad_datetimes_with_nan(bad);
         println!("{}", ctl);
 
@@ -676,7 +4658,7 @@ mod tests {
         ctl.load = smooth;
 
         // plot the filtered and smooth load series
-        ctl.plotly_plot_datetime("./test/parallel_timeload_processed.svg")
+        ctl.plotly_plot_datetime("./test/parallel_timeload_processed.html", false)
             .unwrap();
 
         // save the filtered and smooth load series
@@ -702,6 +4684,15 @@ fn bench_mavg_parallel_fold(b: &mut test::Bencher) {
     });
 }
 
+#[bench]
+fn bench_mavg_parallel(b: &mut test::Bencher) {
+    let v = vec![1000.; 1E+5 as usize];
+    let w = make_window(3., 1., 180usize);
+    b.iter(|| {
+        mavg_parallel(&v, &w, 1usize, 1f64);
+    });
+}
+
 #[bench]
 fn bench_mavg(b: &mut test::Bencher) {
     let v = vec![1000.; 1E+5 as usize];
@@ -710,3 +4701,95 @@ fn bench_mavg(b: &mut test::Bencher) {
         mavg(&v, &w, 1usize, 1f64);
     });
 }
+
+// Sweep of the data length and the window half width for mavg and mavg_parallel,
+// to find the work (length times window) where the parallel one starts to pay off,
+// see MAVG_PARALLEL_MIN_WORK.
+#[cfg(test)]
+fn bench_mavg_crossover(b: &mut test::Bencher, len: usize, half_width: usize, parallel: bool) {
+    let v: Vec<f64> = (0..len).map(|i| 1000. + (i % 7) as f64).collect();
+    let w = make_window(3., 1., half_width);
+    if parallel {
+        b.iter(|| mavg_parallel(&v, &w, 1usize, 1f64));
+    } else {
+        b.iter(|| mavg(&v, &w, 1usize, 1f64));
+    }
+}
+
+#[bench]
+fn bench_mavg_crossover_1e3_10(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 1_000, 10, false);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e3_10_parallel(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 1_000, 10, true);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e3_180(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 1_000, 180, false);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e3_180_parallel(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 1_000, 180, true);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e4_10(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 10_000, 10, false);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e4_10_parallel(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 10_000, 10, true);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e4_180(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 10_000, 180, false);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e4_180_parallel(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 10_000, 180, true);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e5_10(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 100_000, 10, false);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e5_10_parallel(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 100_000, 10, true);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e5_180(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 100_000, 180, false);
+}
+
+#[bench]
+fn bench_mavg_crossover_1e5_180_parallel(b: &mut test::Bencher) {
+    bench_mavg_crossover(b, 100_000, 180, true);
+}
+
+#[bench]
+fn bench_discharge_by_index_presorted(b: &mut test::Bencher) {
+    let v = vec![1000.; 1E+5 as usize];
+    let indices: Vec<usize> = (0..1E+5 as usize).step_by(3).collect();
+    b.iter(|| {
+        discharge_by_index(&v, &indices);
+    });
+}
+
+#[bench]
+fn bench_discharge_by_index_unsorted(b: &mut test::Bencher) {
+    let v = vec![1000.; 1E+5 as usize];
+    let indices: Vec<usize> = (0..1E+5 as usize).step_by(3).rev().collect();
+    b.iter(|| {
+        discharge_by_index(&v, &indices);
+    });
+}
@@ -1,12 +1,37 @@
-use super::VERSION;
+use super::{ErrorCodes, ERROR_FLT_PARSE, VERSION};
 use chrono::prelude::*;
-use clap::{Arg, Command};
+use clap::{value_parser, Arg, Command};
+use serde::Serialize;
+use std::io::Write;
+
+/// Number of characters before the numerical value in the DAD141 replies,
+/// i.e., the description of the value (e.g., GN for GetNet).
+pub const DAD141_PREFIX_LEN: usize = 2;
+
+/// The CLI arguments of the logging application, see parse_cli_log.
+#[derive(Debug, Clone)]
+pub struct LogCli {
+    pub csv_name: String,
+    pub ip: String,
+    pub port: u16,
+    pub tcmd_str: String,
+    pub minutes: u32,
+    pub delay: u64,
+    pub prefix_len: Option<usize>,
+    pub simulate: bool,
+    pub max_samples: Option<u64>,
+    pub duration: Option<u32>,
+    pub fsync: bool,
+    pub status_file: Option<String>,
+    pub error_base: f64,
+    pub verbose: bool,
+}
 
 /// Takes the CLI arguments to control the logging application.
 /// Use hours (times 60) if given, otherwise use minutes.
 /// When both are given, the last given is considered (overriding behavior).
 /// Minutes and hours can be safely unwrapped, the list of possible values is enforced by clap itself.
-pub fn parse_cli_log() -> (String, String, u16, String, u32, u64, bool) {
+pub fn parse_cli_log() -> LogCli {
     let arg_csvfile = Arg::new("csvfile")
         .help("name for the csv file")
         .short('o')
@@ -52,6 +77,48 @@ pub fn parse_cli_log() -> (String, String, u16, String, u32, u64, bool) {
         .short('d')
         .long("delay")
         .default_value("0");
+    let arg_prefix_len = Arg::new("prefix_len")
+        .help("length of the prefix before the value in the replies, or auto to detect it")
+        .long("prefix_len")
+        .alias("prefix-len")
+        .num_args(1)
+        .default_value("auto");
+    let arg_simulate = Arg::new("simulate")
+        .help("simulate the readings without connection, e.g., to test the logging")
+        .long("simulate")
+        .num_args(0)
+        .required(false);
+    let arg_max_samples = Arg::new("max_samples")
+        .help("stop after this number of readings, otherwise log forever")
+        .long("max_samples")
+        .alias("max-samples")
+        .num_args(1)
+        .value_parser(value_parser!(u64).range(1..))
+        .required(false);
+    let arg_duration = Arg::new("duration")
+        .help("stop after this duration in minutes from the first reading, otherwise log forever")
+        .long("duration")
+        .num_args(1)
+        .value_parser(value_parser!(u32).range(1..))
+        .required(false);
+    let arg_fsync = Arg::new("fsync")
+        .help("sync the csv file to disk after each reading, safer on power loss but more writes")
+        .long("fsync")
+        .num_args(0)
+        .required(false);
+    let arg_status_file = Arg::new("status_file")
+        .help("json file with the last successful reading, updated after each one for monitoring")
+        .long("status_file")
+        .alias("status-file")
+        .num_args(1)
+        .required(false);
+    let arg_error_base = Arg::new("error_base")
+        .help("base of the reserved error codes, from base + 5 to base + 9, above any valid load")
+        .long("error_base")
+        .alias("error-base")
+        .num_args(1)
+        .value_parser(value_parser!(f64))
+        .default_value("999990");
     let arg_verbose = Arg::new("verbose")
         .help("print verbose information")
         .short('v')
@@ -67,6 +134,13 @@ pub fn parse_cli_log() -> (String, String, u16, String, u32, u64, bool) {
         .arg(arg_hours)
         .arg(arg_tcmd)
         .arg(arg_delay)
+        .arg(arg_prefix_len)
+        .arg(arg_simulate)
+        .arg(arg_max_samples)
+        .arg(arg_duration)
+        .arg(arg_fsync)
+        .arg(arg_status_file)
+        .arg(arg_error_base)
         .arg(arg_verbose)
         .arg(arg_ip)
         .arg(arg_port)
@@ -86,6 +160,23 @@ pub fn parse_cli_log() -> (String, String, u16, String, u32, u64, bool) {
         .to_owned()
         .parse::<u64>()
         .expect("invalid delay argument, could not parse string to u64");
+    let val_prefix_len: Option<usize> = match cli_args
+        .get_one::<String>("prefix_len")
+        .unwrap()
+        .as_str()
+    {
+        "auto" => None,
+        s => Some(
+            s.parse::<usize>()
+                .expect("invalid prefix_len argument, could not parse string to usize"),
+        ),
+    };
+    let val_simulate: bool = cli_args.get_flag("simulate");
+    let val_max_samples: Option<u64> = cli_args.get_one::<u64>("max_samples").copied();
+    let val_duration: Option<u32> = cli_args.get_one::<u32>("duration").copied();
+    let val_fsync: bool = cli_args.get_flag("fsync");
+    let val_status_file: Option<String> = cli_args.get_one::<String>("status_file").cloned();
+    let val_error_base = *cli_args.get_one::<f64>("error_base").unwrap();
     let val_verbose: bool = cli_args.contains_id("verbose");
     let val_interval: u32 = match cli_args.get_one::<String>("hours") {
         Some(s) => s.to_owned().parse::<u32>().unwrap() * 60 as u32,
@@ -97,15 +188,22 @@ pub fn parse_cli_log() -> (String, String, u16, String, u32, u64, bool) {
             .unwrap(),
     };
 
-    return (
-        val_csvfile,
-        val_ip,
-        val_port,
-        val_tcmd,
-        val_interval,
-        val_delay,
-        val_verbose,
-    );
+    LogCli {
+        csv_name: val_csvfile,
+        ip: val_ip,
+        port: val_port,
+        tcmd_str: val_tcmd,
+        minutes: val_interval,
+        delay: val_delay,
+        prefix_len: val_prefix_len,
+        simulate: val_simulate,
+        max_samples: val_max_samples,
+        duration: val_duration,
+        fsync: val_fsync,
+        status_file: val_status_file,
+        error_base: val_error_base,
+        verbose: val_verbose,
+    }
 }
 
 pub fn prepare_csvfile(file: &str) -> std::fs::File {
@@ -125,6 +223,60 @@ pub fn prepare_csvfile(file: &str) -> std::fs::File {
     return file;
 }
 
+/// Write a line to the csv file and flush it, so that at most the line in progress is lost.
+/// With fsync, also wait for the data to reach the disk, at the cost of more writes (SD cards).
+pub fn write_csv_line(file: &mut std::fs::File, line: &str, fsync: bool) -> std::io::Result<()> {
+    file.write_all(line.as_bytes())?;
+    file.flush()?;
+    if fsync {
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+/// Status of the logger after the last successful reading, for external monitoring.
+#[derive(Debug, Serialize)]
+pub struct LoggerStatus<'a> {
+    pub datetime: &'a str,
+    pub load_kg: f64,
+    pub connected: bool,
+}
+
+/// Write the status as json, first to a temporary file that is then renamed,
+/// so that a watchdog never reads a partially written status.
+pub fn write_status_file(file: &str, status: &LoggerStatus) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp", file);
+    std::fs::write(&tmp, serde_json::to_string(status)?)?;
+    std::fs::rename(&tmp, file)
+}
+
+/// Exponential backoff for the reconnection attempts, starting from base and capped at max,
+/// plus a jitter of up to 10% given as a fraction in [0, 1).
+/// The attempts count from 0 and are reset by the caller after a successful connection.
+pub fn reconnect_backoff(
+    attempt: u32,
+    base: std::time::Duration,
+    max: std::time::Duration,
+    jitter: f64,
+) -> std::time::Duration {
+    let backoff = base.saturating_mul(2u32.saturating_pow(attempt)).min(max);
+    backoff.mul_f64(1. + 0.1 * jitter.clamp(0., 1.))
+}
+
+/// Whether the logging should stop before the next reading at datetime_next,
+/// after the given number of samples and with the optional limits.
+/// Without limits, the logging goes on forever.
+pub fn logging_done(
+    samples: u64,
+    max_samples: Option<u64>,
+    datetime_next: DateTime<Local>,
+    datetime_end: Option<DateTime<Local>>,
+) -> bool {
+    let samples_done = max_samples.is_some_and(|m| samples >= m);
+    let duration_done = datetime_end.is_some_and(|e| datetime_next >= e);
+    samples_done | duration_done
+}
+
 pub fn chrono_first_rounded(
     datetime: DateTime<Local>,
     rounding: chrono::Duration,
@@ -136,3 +288,64 @@ pub fn chrono_first_rounded(
     let first_local = Local.timestamp_opt(first_sec, 0).unwrap();
     first_local
 }
+
+/// Parse the load value from the (trimmed) DAD141 reply,
+/// skipping the description prefix of DAD141_PREFIX_LEN characters.
+/// Return ERROR_FLT_PARSE if the reply is too short or the value can not be parsed.
+pub fn parse_dad141_load(raw_reading: &str) -> f64 {
+    parse_dad141_load_with_prefix(raw_reading, Some(DAD141_PREFIX_LEN))
+}
+
+/// As parse_dad141_load, with the given prefix length, which depends on the firmware,
+/// or, if None, skipping the leading characters that can not start a number (auto-detect).
+pub fn parse_dad141_load_with_prefix(raw_reading: &str, prefix_len: Option<usize>) -> f64 {
+    try_parse_dad141_load(raw_reading, prefix_len).unwrap_or(ERROR_FLT_PARSE)
+}
+
+fn try_parse_dad141_load(raw_reading: &str, prefix_len: Option<usize>) -> Option<f64> {
+    let value = match prefix_len {
+        Some(n) => raw_reading.get(n..),
+        None => Some(raw_reading.trim_start_matches(|c: char| {
+            !(c.is_ascii_digit() || c == '+' || c == '-' || c == '.')
+        })),
+    };
+    value.and_then(|s| s.parse().ok())
+}
+
+/// Decode the bytes of a DAD141 reply into the load and the raw reading to be logged.
+/// An empty (or blank) reply gives the none code and a non-UTF8 reply the invalid code,
+/// with the corresponding error strings as raw reading;
+/// a short or garbled reply keeps its (trimmed) text and gives the parse code.
+/// See parse_dad141_load_with_prefix for the prefix length.
+pub fn decode_reading(buf: &[u8], prefix_len: Option<usize>, codes: &ErrorCodes) -> (f64, String) {
+    match std::str::from_utf8(buf) {
+        Ok(s) if s.trim_end().is_empty() => (codes.none, codes.to_str(codes.none)),
+        Ok(s) => {
+            let raw_reading = s.trim_end();
+            let w = try_parse_dad141_load(raw_reading, prefix_len).unwrap_or(codes.parse);
+            (w, raw_reading.to_string())
+        }
+        Err(_) => (codes.invalid, codes.to_str(codes.invalid)),
+    }
+}
+
+/// Simulate a DAD141 reply for the given datetime, with the given prefix (e.g., GN),
+/// as a daily sine around a baseline with some noise, repeatable for the same datetime.
+/// Used by load_log --simulate to test the logging and processing without the hardware.
+pub fn simulate_reading(datetime: DateTime<Local>, prefix: &str) -> String {
+    let baseline = 13000.;
+    let amplitude = 50.;
+    let noise_amplitude = 2.;
+    let t = datetime.timestamp();
+    let day_fraction = t.rem_euclid(86400) as f64 / 86400.;
+    // xorshift of the timestamp for a noise in [-1, 1]
+    let mut x = (t as u64) ^ 0x9E37_79B9_7F4A_7C15;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    let noise = (x % 2001) as f64 / 1000. - 1.;
+    let load = baseline
+        + amplitude * (2. * std::f64::consts::PI * day_fraction).sin()
+        + noise_amplitude * noise;
+    format!("{}{:+09.1}", prefix, load)
+}
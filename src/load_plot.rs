@@ -6,7 +6,7 @@ use std::path::PathBuf;
 /// It is safe to unwrap clap cli_args.get_one when a default is given
 /// because the default will be used when no argument is passed (i.e., it is always Some<T>).
 /// svgout does not have a default because it is defined based on the csvin name
-pub fn parse_cli() -> (PathBuf, PathBuf) {
+pub fn parse_cli() -> (PathBuf, PathBuf, Option<(f64, f64)>, bool, bool) {
 
     let arg_csvin = Arg::new("input_csvfile")
         .help("name for the csv file")
@@ -17,18 +17,44 @@ pub fn parse_cli() -> (PathBuf, PathBuf) {
         .default_value("loadcells.csv");
 
     let arg_svgout = Arg::new("output_svgfile")
-        .help("name of the output svg file")
+        .help("name of the output file, the extension selects the format: html, svg, or png")
         .short('o')
         .long("svgfile")
         .value_parser(value_parser!(PathBuf))
         .num_args(1);
 
+    let arg_y_range = Arg::new("y_range")
+        .help("fixed range of the load axis, e.g., to compare plots, otherwise from the data")
+        .long("y_range")
+        .alias("y-range")
+        .num_args(2)
+        .value_names(["MIN", "MAX"])
+        .value_parser(value_parser!(f64))
+        .allow_hyphen_values(true)
+        .required(false);
+
+    let arg_show = Arg::new("show")
+        .help("also open the plot in the browser")
+        .long("show")
+        .num_args(0)
+        .required(false);
+
+    let arg_quiet = Arg::new("quiet")
+        .help("do not print progress messages")
+        .short('q')
+        .long("quiet")
+        .num_args(0)
+        .required(false);
+
     let cli_args = Command::new("Flintec_plot")
         .version(VERSION.unwrap_or("unknown"))
         .author("Luca Peruzzo")
         .about("cli app to plot the load time series")
         .arg(arg_csvin)
         .arg(arg_svgout)
+        .arg(arg_y_range)
+        .arg(arg_show)
+        .arg(arg_quiet)
         .get_matches();
 
     let csvin: PathBuf = cli_args
@@ -41,7 +67,18 @@ pub fn parse_cli() -> (PathBuf, PathBuf) {
         None => csvin.with_extension("svg"),
     };
 
-    println!("read from {:?} and save to {:?}", csvin, svgout);
+    let y_range: Option<(f64, f64)> = cli_args.get_many::<f64>("y_range").map(|mut r| {
+        let min = *r.next().unwrap();
+        let max = *r.next().unwrap();
+        (min, max)
+    });
+
+    let show: bool = cli_args.get_flag("show");
+    let quiet: bool = cli_args.get_flag("quiet");
+
+    if !quiet {
+        eprintln!("read from {:?} and save to {:?}", csvin, svgout);
+    }
 
-    return (csvin, svgout);
+    (csvin, svgout, y_range, show, quiet)
 }
@@ -1,8 +1,97 @@
 use super::VERSION;
+#[cfg(feature = "fft")]
+use crate::dominant_period;
+use crate::{
+    discharge_by_index, ewma_time, find_anomalies, hampel as hampel_filter, make_window,
+    mavg_auto, mavg_causal, quality_flags, read_bad_datetimes, read_bad_intervals,
+    rolling_median, setnan_by_index, DupPolicy, ErrorCodes, ErrorKind, NanPolicy, RawReading,
+    TimeLoad,
+};
 use chrono::prelude::*;
+use clap::builder::RangedU64ValueParser;
 use clap::{value_parser, Arg, Command};
+use std::error::Error;
 use std::path::PathBuf;
 
+/// Number of stages of load_process, see ProcessStage::index.
+pub const PROCESS_STAGES: usize = 6;
+
+/// The stages of load_process with their counts, passed to a progress callback,
+/// e.g., for a GUI wrapper to render a progress bar without parsing the messages.
+/// Anomaly is given only if the anomaly detection is enabled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessStage {
+    Read { rows: usize },
+    Fill { rows: usize, filled: usize },
+    Mask { rows: usize, nan: usize },
+    Anomaly { anomalies: usize },
+    Smooth { rows: usize, remaining_nan: usize },
+    Write { rows: usize },
+}
+
+impl ProcessStage {
+    /// Position of the stage, from 0 to PROCESS_STAGES - 1.
+    pub fn index(&self) -> usize {
+        match self {
+            ProcessStage::Read { .. } => 0,
+            ProcessStage::Fill { .. } => 1,
+            ProcessStage::Mask { .. } => 2,
+            ProcessStage::Anomaly { .. } => 3,
+            ProcessStage::Smooth { .. } => 4,
+            ProcessStage::Write { .. } => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessStage::Read { rows } => write!(f, "read {} rows", rows),
+            ProcessStage::Fill { rows, filled } => {
+                write!(f, "filled {} missing datetimes, {} rows", filled, rows)
+            }
+            ProcessStage::Mask { rows, nan } => write!(f, "masked, {} nan of {} rows", nan, rows),
+            ProcessStage::Anomaly { anomalies } => write!(f, "found {} anomalies", anomalies),
+            ProcessStage::Smooth { rows, remaining_nan } => {
+                write!(f, "smoothed, {} nan of {} rows remain", remaining_nan, rows)
+            }
+            ProcessStage::Write { rows } => write!(f, "wrote {} rows", rows),
+        }
+    }
+}
+
+/// Default progress callback, print the stage and its position to stderr.
+pub fn print_progress(stage: &ProcessStage) {
+    eprintln!("[{}/{}] {}", stage.index() + 1, PROCESS_STAGES, stage);
+}
+
+/// Print the histogram of the loads to stderr, one bin per line with a bar of its count.
+pub fn print_histogram(histogram: &[(f64, f64, usize)]) {
+    let max_count = histogram.iter().map(|b| b.2).max().unwrap_or(0);
+    for (low, high, count) in histogram.iter() {
+        let bar = (count * 50).checked_div(max_count).unwrap_or(0);
+        eprintln!("{:>12.2} - {:>12.2} {:>8} {}", low, high, count, "#".repeat(bar));
+    }
+}
+
+/// Parse a strictly positive number, e.g., a time constant.
+fn parse_positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v > 0. => Ok(v),
+        Ok(_) => Err(String::from("must be positive")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse an odd window width, i.e., centered on the sample, at least 1.
+fn parse_odd_width(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(w) if w % 2 == 1 => Ok(w),
+        Ok(_) => Err(String::from("must be odd and at least 1")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 /// Takes the CLI arguments to set the processing parameters.
 pub fn parse_cli() -> (
     PathBuf,
@@ -13,13 +102,33 @@ pub fn parse_cli() -> (
     f64,
     f64,
     bool,
+    Option<f64>,
+    bool,
+    bool,
     usize,
     f64,
     f64,
     f64,
+    bool,
+    usize,
+    bool,
+    usize,
+    f64,
+    Option<PathBuf>,
     Option<PathBuf>,
     Option<(NaiveTime, NaiveTime)>,
     i32,
+    Option<i64>,
+    Option<DateTime<FixedOffset>>,
+    Option<DateTime<FixedOffset>>,
+    Option<i64>,
+    f64,
+    Option<(f64, f64)>,
+    bool,
+    bool,
+    bool,
+    Option<usize>,
+    bool,
     bool,
 ) {
     let arg_in_raw_data = Arg::new("in_raw_data")
@@ -66,10 +175,27 @@ pub fn parse_cli() -> (
         .num_args(1)
         .value_parser(value_parser!(f64))
         .default_value("1");
+    let arg_mavg_causal = Arg::new("mavg_causal")
+        .help("use only the past side of the mavg window, as at the live edge, with a phase lag")
+        .long("mavg_causal")
+        .num_args(0)
+        .conflicts_with("ewma")
+        .required(false);
+    let arg_ewma = Arg::new("ewma")
+        .help("time constant in minutes for an exponential weighted moving average instead of mavg")
+        .long("ewma")
+        .num_args(1)
+        .value_parser(parse_positive_f64)
+        .required(false);
     let arg_anomaly_detect = Arg::new("anomaly_detect")
         .long("anomaly_detect")
         .num_args(0)
         .help("find and remove anomalous periods");
+    let arg_drop_anomalies = Arg::new("drop_anomalies")
+        .long("drop_anomalies")
+        .alias("drop-anomalies")
+        .num_args(0)
+        .help("with anomaly_detect, remove the anomalous rows after the smoothing, not just nan");
     let arg_anomaly_width = Arg::new("anomaly_width")
         .help("width of the anomaly detection window")
         .long("anomaly_width")
@@ -82,6 +208,32 @@ pub fn parse_cli() -> (
         .help("threshold for the anomaly detection as interquartile range")
         .value_parser(value_parser!(f64))
         .default_value("40");
+    let arg_despike = Arg::new("despike")
+        .long("despike")
+        .num_args(0)
+        .help("remove isolated spikes with a rolling median before the moving average");
+    let arg_despike_width = Arg::new("despike_width")
+        .help("width of the rolling median window, odd")
+        .long("despike_width")
+        .num_args(1)
+        .value_parser(parse_odd_width)
+        .default_value("5");
+    let arg_hampel = Arg::new("hampel")
+        .long("hampel")
+        .num_args(0)
+        .help("replace the outliers with their rolling median (Hampel filter) before the mavg");
+    let arg_hampel_width = Arg::new("hampel_width")
+        .help("width of the Hampel filter window, odd")
+        .long("hampel_width")
+        .num_args(1)
+        .value_parser(parse_odd_width)
+        .default_value("7");
+    let arg_hampel_sigmas = Arg::new("hampel_sigmas")
+        .help("threshold of the Hampel filter in standard deviations, estimated from the MAD")
+        .long("hampel_sigmas")
+        .num_args(1)
+        .value_parser(value_parser!(f64))
+        .default_value("3");
     let arg_max_load = Arg::new("max_load")
         .help("maximum accepted load value")
         .long("max_load")
@@ -100,6 +252,12 @@ pub fn parse_cli() -> (
         .num_args(0..2)
         .value_parser(value_parser!(PathBuf))
         .required(false);
+    let arg_bad_intervals = Arg::new("bad_intervals")
+        .help("name of the file with bad intervals (start,end per line) to be removed")
+        .long("bad_intervals")
+        .num_args(1)
+        .value_parser(value_parser!(PathBuf))
+        .required(false);
     let arg_bad_time_interval = Arg::new("bad_time_interval")
         .help("daily time interval to be removed")
         .long("bad_time_interval")
@@ -112,12 +270,80 @@ pub fn parse_cli() -> (
         .num_args(1)
         .value_parser(value_parser!(i32))
         .default_value("-8");
+    let arg_step = Arg::new("step")
+        .help("logging interval in minutes, otherwise the smallest time step in the data is used")
+        .long("step")
+        .num_args(1)
+        .value_parser(value_parser!(i64).range(1..))
+        .required(false);
+    let arg_start = Arg::new("start")
+        .help("process only the datetimes from this one (included), in RFC 3339")
+        .long("start")
+        .num_args(1)
+        .value_parser(DateTime::parse_from_rfc3339)
+        .required(false);
+    let arg_end = Arg::new("end")
+        .help("process only the datetimes before this one (excluded), in RFC 3339")
+        .long("end")
+        .num_args(1)
+        .value_parser(DateTime::parse_from_rfc3339)
+        .required(false);
+    let arg_resample = Arg::new("resample")
+        .help("downsample to this interval in minutes after smoothing, e.g., 60 for hourly means")
+        .long("resample")
+        .num_args(1)
+        .value_parser(value_parser!(i64).range(1..))
+        .conflicts_with("raw_column")
+        .required(false);
+    let arg_error_base = Arg::new("error_base")
+        .help("base of the error codes of the logger, from base + 5 to base + 9")
+        .long("error_base")
+        .alias("error-base")
+        .num_args(1)
+        .value_parser(value_parser!(f64))
+        .default_value("999990");
+    let arg_calibrate = Arg::new("calibrate")
+        .help("convert the raw loads as slope * load + intercept, given as slope,intercept")
+        .long("calibrate")
+        .num_args(1)
+        .value_delimiter(',')
+        .allow_hyphen_values(true)
+        .value_parser(value_parser!(f64))
+        .required(false);
     let arg_verbose = Arg::new("verbose")
         .help("print verbose information")
         .short('v')
         .long("verbose")
         .num_args(0..)
         .required(false);
+    let arg_raw_column = Arg::new("raw_column")
+        .help("keep the raw_reading column of the logger in the output, blank for filled values")
+        .long("raw_column")
+        .num_args(0)
+        .required(false);
+    let arg_quality = Arg::new("quality")
+        .help("add the quality column: measured, filled, masked, anomaly, or replaced")
+        .long("quality")
+        .num_args(0)
+        .conflicts_with_all(["raw_column", "resample"])
+        .required(false);
+    let arg_stats = Arg::new("stats")
+        .help("print the summary statistics of the input and processed loads to stderr")
+        .long("stats")
+        .num_args(0)
+        .required(false);
+    let arg_histogram = Arg::new("histogram")
+        .help("print the histogram of the processed loads with this number of bins to stderr")
+        .long("histogram")
+        .num_args(1)
+        .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+        .required(false);
+    let arg_quiet = Arg::new("quiet")
+        .help("do not print the processing steps, warnings are still printed to stderr")
+        .short('q')
+        .long("quiet")
+        .num_args(0)
+        .required(false);
     let cli_args = Command::new("Flintec_process")
         .version(VERSION.unwrap_or("unknown"))
         .author("Luca Peruzzo")
@@ -129,15 +355,35 @@ pub fn parse_cli() -> (
         .arg(arg_mavg_max_missing_weight)
         .arg(arg_mavg_central_weight)
         .arg(arg_mavg_side_weight)
+        .arg(arg_mavg_causal)
+        .arg(arg_ewma)
         .arg(arg_anomaly_detect)
+        .arg(arg_drop_anomalies)
         .arg(arg_anomaly_width)
         .arg(arg_anomaly_iqr)
+        .arg(arg_despike)
+        .arg(arg_despike_width)
+        .arg(arg_hampel)
+        .arg(arg_hampel_width)
+        .arg(arg_hampel_sigmas)
         .arg(arg_max_load)
         .arg(arg_min_load)
         .arg(arg_bad_datetimes)
+        .arg(arg_bad_intervals)
         .arg(arg_bad_time_interval)
         .arg(arg_timezone)
+        .arg(arg_step)
+        .arg(arg_start)
+        .arg(arg_end)
+        .arg(arg_resample)
+        .arg(arg_error_base)
+        .arg(arg_calibrate)
+        .arg(arg_raw_column)
+        .arg(arg_quality)
+        .arg(arg_stats)
+        .arg(arg_histogram)
         .arg(arg_verbose)
+        .arg(arg_quiet)
         .get_matches();
     let csvin = cli_args
         .get_one::<PathBuf>("in_raw_data")
@@ -162,14 +408,25 @@ pub fn parse_cli() -> (
     let mavg_max_missing_weight = *cli_args.get_one::<f64>("mavg_max_missing_weight").unwrap();
     let mavg_central_weight = *cli_args.get_one::<f64>("mavg_central_weight").unwrap();
     let mavg_side_weight = *cli_args.get_one::<f64>("mavg_side_weight").unwrap();
-    let anomaly_detect = cli_args.contains_id("anomaly_detect");
+    let mavg_causal: bool = cli_args.get_flag("mavg_causal");
+    let ewma: Option<f64> = cli_args.get_one::<f64>("ewma").copied();
+    let anomaly_detect: bool = cli_args.get_flag("anomaly_detect");
+    let drop_anomalies: bool = cli_args.get_flag("drop_anomalies");
     let anomaly_width = *cli_args.get_one::<usize>("anomaly_width").unwrap();
     let anomaly_iqr = *cli_args.get_one::<f64>("anomaly_iqr").unwrap();
+    let despike: bool = cli_args.get_flag("despike");
+    let despike_width = *cli_args.get_one::<usize>("despike_width").unwrap();
+    let hampel: bool = cli_args.get_flag("hampel");
+    let hampel_width = *cli_args.get_one::<usize>("hampel_width").unwrap();
+    let hampel_sigmas = *cli_args.get_one::<f64>("hampel_sigmas").unwrap();
     let max_load = *cli_args.get_one::<f64>("max_load").unwrap();
     let min_load = *cli_args.get_one::<f64>("min_load").unwrap();
     let bad_datetimes: Option<PathBuf> = cli_args
         .get_one::<PathBuf>("bad_datetimes")
         .map(|p| p.clone());
+    let bad_intervals: Option<PathBuf> = cli_args
+        .get_one::<PathBuf>("bad_intervals")
+        .cloned();
     let bad_time_interval: Option<(NaiveTime, NaiveTime)> =
         match cli_args.get_many::<String>("bad_time_interval") {
             Some(mut ti) => {
@@ -183,7 +440,28 @@ pub fn parse_cli() -> (
         };
 
     let timezone = *cli_args.get_one::<i32>("timezone").unwrap();
+    let step: Option<i64> = cli_args.get_one::<i64>("step").copied();
+    let start: Option<DateTime<FixedOffset>> =
+        cli_args.get_one::<DateTime<FixedOffset>>("start").copied();
+    let end: Option<DateTime<FixedOffset>> =
+        cli_args.get_one::<DateTime<FixedOffset>>("end").copied();
+    let resample: Option<i64> = cli_args.get_one::<i64>("resample").copied();
+    let error_base = *cli_args.get_one::<f64>("error_base").unwrap();
+    let calibrate: Option<(f64, f64)> = cli_args.get_many::<f64>("calibrate").map(|c| {
+        match c.copied().collect::<Vec<f64>>()[..] {
+            [slope, intercept] => (slope, intercept),
+            _ => {
+                eprintln!("error, --calibrate expects two values as slope,intercept");
+                std::process::exit(1);
+            }
+        }
+    });
+    let raw_column: bool = cli_args.get_flag("raw_column");
+    let quality: bool = cli_args.get_flag("quality");
+    let stats: bool = cli_args.get_flag("stats");
+    let histogram: Option<usize> = cli_args.get_one::<usize>("histogram").copied();
     let verbose: bool = cli_args.contains_id("verbose");
+    let quiet: bool = cli_args.get_flag("quiet");
 
     return (
         csvin,
@@ -193,14 +471,528 @@ pub fn parse_cli() -> (
         mavg_max_missing_weight,
         mavg_central_weight,
         mavg_side_weight,
+        mavg_causal,
+        ewma,
+        anomaly_detect,
+        drop_anomalies,
+        anomaly_width,
+        anomaly_iqr,
+        min_load,
+        max_load,
+        despike,
+        despike_width,
+        hampel,
+        hampel_width,
+        hampel_sigmas,
+        bad_datetimes,
+        bad_intervals,
+        bad_time_interval,
+        timezone,
+        step,
+        start,
+        end,
+        resample,
+        error_base,
+        calibrate,
+        raw_column,
+        quality,
+        stats,
+        histogram,
+        verbose,
+        quiet,
+    );
+}
+
+/// The parameters of load_process, see parse_cli for their meaning.
+/// The default is that of the CLI, with empty input and output file names.
+#[derive(Debug, Clone)]
+pub struct ProcessConfig {
+    pub csvin: PathBuf,
+    pub csvout: PathBuf,
+    pub side: usize,
+    pub mavg_max_missing_values: usize,
+    pub mavg_max_missing_pct_weight: f64,
+    pub mavg_central_weight: f64,
+    pub mavg_side_weight: f64,
+    pub mavg_causal: bool,
+    pub ewma: Option<f64>,
+    pub anomaly_detect: bool,
+    pub drop_anomalies: bool,
+    pub anomaly_width: usize,
+    pub anomaly_iqr: f64,
+    pub min_load: f64,
+    pub max_load: f64,
+    pub despike: bool,
+    pub despike_width: usize,
+    pub hampel: bool,
+    pub hampel_width: usize,
+    pub hampel_sigmas: f64,
+    pub bad_datetimes: Option<PathBuf>,
+    pub bad_intervals: Option<PathBuf>,
+    pub bad_time_interval: Option<(NaiveTime, NaiveTime)>,
+    pub timezone: i32,
+    pub step: Option<i64>,
+    pub start: Option<DateTime<FixedOffset>>,
+    pub end: Option<DateTime<FixedOffset>>,
+    pub resample: Option<i64>,
+    pub error_base: f64,
+    pub calibrate: Option<(f64, f64)>,
+    pub raw_column: bool,
+    pub quality: bool,
+    pub stats: bool,
+    pub histogram: Option<usize>,
+    pub verbose: bool,
+    pub quiet: bool,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        ProcessConfig {
+            csvin: PathBuf::new(),
+            csvout: PathBuf::new(),
+            side: 2,
+            mavg_max_missing_values: 3,
+            mavg_max_missing_pct_weight: 80.,
+            mavg_central_weight: 3.,
+            mavg_side_weight: 1.,
+            mavg_causal: false,
+            ewma: None,
+            anomaly_detect: false,
+            drop_anomalies: false,
+            anomaly_width: 16,
+            anomaly_iqr: 40.,
+            min_load: 13000.,
+            max_load: 17000.,
+            despike: false,
+            despike_width: 5,
+            hampel: false,
+            hampel_width: 7,
+            hampel_sigmas: 3.,
+            bad_datetimes: None,
+            bad_intervals: None,
+            bad_time_interval: None,
+            timezone: -8,
+            step: None,
+            start: None,
+            end: None,
+            resample: None,
+            error_base: 999990.,
+            calibrate: None,
+            raw_column: false,
+            quality: false,
+            stats: false,
+            histogram: None,
+            verbose: false,
+            quiet: false,
+        }
+    }
+}
+
+/// Process the load time series of config.csvin into config.csvout:
+/// filter, refill, and smooth, as the load_process CLI.
+/// on_stage is called at the end of each stage, in order, see ProcessStage,
+/// e.g., print_progress, or a GUI wrapper rendering a progress bar.
+pub fn run(config: ProcessConfig, on_stage: &dyn Fn(&ProcessStage)) -> Result<(), Box<dyn Error>> {
+    let ProcessConfig {
+        csvin,
+        csvout,
+        side,
+        mavg_max_missing_values,
+        mavg_max_missing_pct_weight,
+        mavg_central_weight,
+        mavg_side_weight,
+        mavg_causal: causal,
+        ewma,
         anomaly_detect,
+        drop_anomalies,
         anomaly_width,
         anomaly_iqr,
         min_load,
         max_load,
+        despike,
+        despike_width,
+        hampel,
+        hampel_width,
+        hampel_sigmas,
         bad_datetimes,
+        bad_intervals,
         bad_time_interval,
         timezone,
+        step,
+        start,
+        end,
+        resample,
+        error_base,
+        calibrate,
+        raw_column,
+        quality,
+        stats,
+        histogram,
         verbose,
+        quiet,
+    } = config;
+
+    // progress messages go to stderr so that stdout is kept clean for data
+    macro_rules! progress {
+        ($($arg:tt)*) => {
+            if !quiet {
+                eprintln!($($arg)*);
+            }
+        };
+    }
+
+    progress!(
+        "Reading time series in RFC3339 - ISO8601 and resetting to timezone {}",
+        timezone
     );
+
+    if verbose {
+        eprintln!("csvin {:?}", csvin);
+        eprintln!("csvout {:?}", csvout);
+        eprintln!("side {}", side);
+        eprintln!("mavg_max_missing_values {}", mavg_max_missing_values);
+        eprintln!(
+            "mavg_max_missing_pct_weight {}",
+            mavg_max_missing_pct_weight
+        );
+        eprintln!("mavg_central_weight {}", mavg_central_weight);
+        eprintln!("mavg_side_weight {}", mavg_side_weight);
+        eprintln!("mavg_causal {}", causal);
+        eprintln!("ewma {:?}", ewma);
+        eprintln!("anomaly_detect {}", anomaly_detect);
+        eprintln!("drop_anomalies {}", drop_anomalies);
+        eprintln!("anomaly_width {}", anomaly_width);
+        eprintln!("anomaly_iqr {}", anomaly_iqr);
+        eprintln!("min_load {}", min_load);
+        eprintln!("max_load {}", max_load);
+        eprintln!("despike {}", despike);
+        eprintln!("despike_width {}", despike_width);
+        eprintln!("hampel {}", hampel);
+        eprintln!("hampel_width {}", hampel_width);
+        eprintln!("hampel_sigmas {}", hampel_sigmas);
+        eprintln!("bad_datetimes {:?}", bad_datetimes);
+        eprintln!("bad_intervals {:?}", bad_intervals);
+        eprintln!("bad_time_interval {:?}", bad_time_interval);
+        eprintln!("timezone {}", timezone);
+        eprintln!("step {:?}", step);
+        eprintln!("start {:?}", start);
+        eprintln!("end {:?}", end);
+        eprintln!("resample {:?}", resample);
+        eprintln!("error_base {}", error_base);
+        eprintln!("calibrate {:?}", calibrate);
+        eprintln!("raw_column {}", raw_column);
+        eprintln!("quality {}", quality);
+        eprintln!("stats {}", stats);
+        eprintln!("histogram {:?}", histogram);
+        eprintln!("verbose {}", verbose);
+        eprintln!("quiet {}", quiet);
+    }
+
+    progress!("> read data from {}", csvin.to_str().unwrap());
+    let csv_info = match TimeLoad::validate_csv(&csvin) {
+        Ok(info) => info,
+        Err(e) => {
+            let fin = csvin.to_str().unwrap();
+            return Err(format!("{} is not a load time series: {}", fin, e).into());
+        }
+    };
+    if verbose {
+        eprintln!("csvin header {:?}", csv_info.header);
+        eprintln!("csvin sampled_rows {}", csv_info.sampled_rows);
+        eprintln!("csvin datetime_format {:?}", csv_info.datetime_format);
+    }
+    if raw_column & ((csv_info.columns < 3) | csv_info.datetime_format.is_some()) {
+        eprintln!(
+            "warning, no raw_reading column in {} as written by load_log, it will be blank",
+            csvin.to_str().unwrap()
+        );
+    }
+    let timezone_seconds = timezone * 60 * 60;
+    let timezone_fixed_offset = FixedOffset::east_opt(timezone_seconds).unwrap();
+
+    // datetimes without offset are taken in the given timezone;
+    // the raw readings are matched by datetime, they survive the filtering and NAN replacements
+    let mut raw_readings: Vec<RawReading> = Vec::new();
+    let read = match csv_info.datetime_format {
+        None if raw_column => TimeLoad::from_csv_with_raw(&csvin).map(|(tl, raw)| {
+            raw_readings = raw;
+            tl
+        }),
+        None => TimeLoad::from_csv_auto(&csvin),
+        Some(f) => TimeLoad::from_csv_with_format(&csvin, f, timezone_fixed_offset),
+    };
+    let mut tl = match read {
+        Ok(tl) => tl,
+        Err(e) => return Err(format!("could not read {}: {}", csvin.to_str().unwrap(), e).into()),
+    };
+
+    on_stage(&ProcessStage::Read { rows: tl.len() });
+
+    tl.to_timezone(timezone_fixed_offset);
+
+    if let Err(e) = tl.try_is_ordered() {
+        eprintln!("warning, {}; sorting by datetime", e);
+        tl.sort_by_time();
+    }
+    let duplicates = tl.dedup_timestamps(DupPolicy::KeepFirst);
+    if duplicates > 0 {
+        eprintln!("warning, removed {} duplicate datetimes, kept the first", duplicates);
+    }
+    tl.is_ordered();
+
+    // after the sort and dedup, the sampling interval is the smallest step between datetimes
+    if stats {
+        eprintln!("statistics of the input loads\n{}", tl.stats());
+    }
+
+    // the missing bounds are the ends of the data, the last datetime included
+    if start.is_some() | end.is_some() {
+        let start = start.or(tl.time.first().copied());
+        let end = end.or(tl.time.last().map(|t| *t + chrono::Duration::seconds(1)));
+        if let (Some(s), Some(e)) = (start, end) {
+            progress!("> limit the processing to the datetimes from {} to {}", s, e);
+            tl = tl.slice_time(s, e);
+        }
+        if tl.is_empty() {
+            return Err("no datetimes in the processing window".into());
+        }
+    }
+
+    let mut ftl = match step {
+        Some(m) => {
+            progress!("> fill missing values with nan, every {} minute(s)", m);
+            tl.fill_missing_with_nan_step(chrono::Duration::minutes(m))
+        }
+        None => {
+            progress!("> fill missing values with nan");
+            tl.fill_missing_with_nan()
+        }
+    };
+
+    progress!("> check that the time series is continuous and ordered");
+    ftl.is_ordered_and_continuous();
+    on_stage(&ProcessStage::Fill {
+        rows: ftl.len(),
+        filled: ftl.len().saturating_sub(tl.len()),
+    });
+
+    if let Some(bdt) = bad_datetimes {
+        let vec_bad_dateimes = match read_bad_datetimes(&bdt) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("in {}: {}", bdt.to_str().unwrap(), e).into()),
+        };
+        progress!(
+            "> found {} bad datetimes in {}, set them to nan",
+            vec_bad_dateimes.len(),
+            bdt.to_str().unwrap()
+        );
+        ftl.replace_bad_datetimes_with_nan(vec_bad_dateimes);
+    }
+
+    if let Some(bi) = bad_intervals {
+        let vec_bad_intervals = match read_bad_intervals(&bi) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("in {}: {}", bi.to_str().unwrap(), e).into()),
+        };
+        progress!(
+            "> found {} bad intervals in {}, set them to nan",
+            vec_bad_intervals.len(),
+            bi.to_str().unwrap()
+        );
+        for (start, end) in vec_bad_intervals.into_iter() {
+            ftl.replace_bad_interval_with_nan(start, end);
+        }
+    }
+
+    if let Some(t) = bad_time_interval {
+        progress!(
+            "> consider daily times between {} and {} as invalid, set them to nan",
+            t.0, t.1
+        );
+        ftl.replace_bad_time_interval_with_nan(t.0, t.1);
+    }
+
+    let codes = ErrorCodes::from_base(error_base);
+    let mut errors: Vec<(ErrorKind, usize)> =
+        ftl.error_summary_with_codes(&codes).into_iter().collect();
+    errors.sort_by_key(|(k, _)| *k as usize);
+    for (kind, count) in errors.iter() {
+        progress!("> found {} error code(s) of kind {:?}", count, kind);
+    }
+    progress!(
+        "> consider the values from {} to {} as error codes, set them to nan",
+        codes.parse, codes.general
+    );
+    ftl.replace_error_codes_with_nan(&codes);
+
+    // the error codes are in raw units, calibrate after removing them
+    if let Some((slope, intercept)) = calibrate {
+        progress!(
+            "> calibrate the loads as {} * load + {}",
+            slope, intercept
+        );
+        ftl.apply_calibration(slope, intercept);
+    }
+
+    progress!(
+        "> consider outliers values below {} or above {}, set them to nan",
+        min_load, max_load
+    );
+    ftl.replace_outliers_with_nan(min_load, max_load);
+    on_stage(&ProcessStage::Mask {
+        rows: ftl.len(),
+        nan: ftl.load.iter().filter(|l| l.is_nan()).count(),
+    });
+
+    // a diurnal cycle is likely an artifact, e.g., temperature, and the smoothing would hide it
+    #[cfg(feature = "fft")]
+    if let Some(period) = dominant_period(&ftl.time, &ftl.load) {
+        progress!("> dominant period of the loads is {} minute(s)", period.num_minutes());
+        if (period >= chrono::Duration::hours(20)) & (period <= chrono::Duration::hours(28)) {
+            eprintln!(
+                "warning, dominant period of {:.1} hours, likely a diurnal artifact",
+                period.num_minutes() as f64 / 60.
+            );
+        }
+    }
+
+    // Optional anomaly detection, save them to file so that they can be added to the bad datetimes.
+    // Meanwhile, set values to nan.
+    // Require at least half of the window width to be valid load values, otherwise skip it.
+    // the anomalies set to nan, for the quality column
+    let mut anomalies: Vec<usize> = Vec::new();
+    progress!("> anomomaly detection is {}", anomaly_detect);
+    if anomaly_detect {
+        let min_data_anomaly = anomaly_width / 2usize;
+        let (anomalies_indices, _) =
+            find_anomalies(&ftl.load, anomaly_width, min_data_anomaly, anomaly_iqr);
+        let mut atl = TimeLoad::new(anomalies_indices.len());
+        for i in anomalies_indices.iter() {
+            atl.time.push(*ftl.time.get(*i).unwrap());
+            atl.load.push(*ftl.load.get(*i).unwrap());
+        }
+        atl.to_csv("./timeload_anomalies.csv");
+        on_stage(&ProcessStage::Anomaly {
+            anomalies: anomalies_indices.len(),
+        });
+        // also the dropped ones are kept as nan through the smoothing, see below
+        setnan_by_index(&mut ftl.load[..], &anomalies_indices);
+        anomalies = anomalies_indices;
+    }
+
+    // the outliers replaced by the hampel filter, for the quality column
+    let mut replaced: Vec<usize> = Vec::new();
+    if hampel {
+        let (filtered, replaced_indices) = hampel_filter(&ftl.load, hampel_width, hampel_sigmas);
+        progress!(
+            "> replace {} outliers with their rolling median, width {} and {} sigmas",
+            replaced_indices.len(),
+            hampel_width,
+            hampel_sigmas
+        );
+        ftl.load = filtered;
+        replaced = replaced_indices;
+    }
+
+    // the nan before the smoothing, to tell the filled loads from the measured ones
+    let unsmoothed = if quality { ftl.load.clone() } else { Vec::new() };
+
+    if despike {
+        progress!(
+            "> remove isolated spikes with a rolling median of width {}",
+            despike_width
+        );
+        ftl.load = rolling_median(&ftl.load, despike_width);
+    }
+
+    if let Some(tau) = ewma {
+        progress!(
+            "> apply exponential weighted moving average with time constant {} minute(s)",
+            tau
+        );
+        let tau = chrono::Duration::milliseconds((tau * 60. * 1000.) as i64);
+        ftl.load = ewma_time(&ftl.time, &ftl.load, tau);
+    } else if (side != 0) & causal {
+        progress!("> apply causal moving average to smooth and fill nan, with a phase lag");
+        let mavg_window = make_window(mavg_central_weight, mavg_side_weight, side);
+        let smooth = mavg_causal(
+            &ftl.load[..],
+            &mavg_window[..=side],
+            mavg_max_missing_values,
+            mavg_max_missing_pct_weight,
+        );
+        ftl.load = smooth;
+    } else if side != 0 {
+        progress!("> apply moving average to smooth and fill nan");
+        let mavg_window = make_window(mavg_central_weight, mavg_side_weight, side);
+        let smooth = mavg_auto(
+            &ftl.load[..],
+            &mavg_window,
+            mavg_max_missing_values,
+            mavg_max_missing_pct_weight,
+        );
+        ftl.load = smooth;
+    }
+
+    let remaining_nan = ftl.load.iter().filter(|l| l.is_nan()).count();
+    if ewma.is_none() & (side == 0) & (remaining_nan > 0) {
+        eprintln!(
+            "warning, no moving average (mavg_side is 0): {} nan values of {} are not filled",
+            remaining_nan,
+            ftl.load.len()
+        );
+    } else {
+        progress!("> {} nan values of {} remain", remaining_nan, ftl.load.len());
+    }
+
+    on_stage(&ProcessStage::Smooth {
+        rows: ftl.len(),
+        remaining_nan,
+    });
+
+    // the quality flags, before dropping the anomalous rows, which would shift the indices
+    let mut flags = if quality {
+        quality_flags(&unsmoothed, &ftl.load, &replaced, &anomalies)
+    } else {
+        Vec::new()
+    };
+
+    // the anomalous rows are dropped only after the smoothing, otherwise the moving average
+    // would span the removed rows as if the loads on their sides were contiguous
+    if drop_anomalies & !anomalies.is_empty() {
+        progress!("> remove the {} rows of the anomalies", anomalies.len());
+        ftl.discharge_indices(&anomalies);
+        if quality {
+            flags = discharge_by_index(&flags, &anomalies);
+        }
+    }
+
+    if let Some(m) = resample {
+        progress!("> resample to the mean of every {} minute(s)", m);
+        let step = chrono::Duration::minutes(m);
+        ftl = match ftl.to_interval(step, NanPolicy::Ignore { min_valid: 1 }) {
+            Ok(rtl) => rtl,
+            Err(e) => return Err(format!("could not resample: {}", e).into()),
+        };
+    }
+
+    if stats {
+        eprintln!("statistics of the processed loads\n{}", ftl.stats());
+    }
+
+    if let Some(bins) = histogram {
+        eprintln!("histogram of the processed loads");
+        print_histogram(&ftl.load_histogram(bins));
+    }
+
+    progress!("> save processed data to {}", csvout.to_str().unwrap());
+    if raw_column {
+        ftl.to_csv_with_raw(csvout, &raw_readings);
+    } else if quality {
+        ftl.to_csv_with_quality(csvout, &flags);
+    } else {
+        ftl.to_csv(csvout);
+    }
+    on_stage(&ProcessStage::Write { rows: ftl.len() });
+    Ok(())
 }
@@ -1,11 +1,11 @@
-use super::VERSION;
+use super::{Aggregator, NanPolicy, VERSION};
 use clap::{value_parser, Arg, Command};
 use std::path::PathBuf;
 
 /// Takes the CLI arguments that control the downsample of the load time series.
 /// It is safe to unwrap clap cli_args.get_one when a default is given
 /// because the default will be used when no argument is passed (i.e., it is always Some<T>).
-pub fn parse_cli() -> (PathBuf, PathBuf) {
+pub fn parse_cli() -> (PathBuf, PathBuf, Aggregator, NanPolicy, bool, Option<i64>, bool) {
 
     let arg_csvin = Arg::new("input_csvfile")
         .help("name for the csv file")
@@ -18,16 +18,67 @@ pub fn parse_cli() -> (PathBuf, PathBuf) {
     let arg_csvout = Arg::new("output_csvfile")
         .help("name of the output csv file")
         .short('o')
-        .long("csvfile")
+        .long("outcsvfile")
         .value_parser(value_parser!(PathBuf))
         .num_args(1);
 
+    let arg_agg = Arg::new("agg")
+        .help("aggregation of the loads of each hour")
+        .long("agg")
+        .num_args(1)
+        .value_parser(["mean", "median", "min", "max", "range", "sum", "first", "last"])
+        .default_value("mean");
+
+    let arg_min_valid = Arg::new("min_valid")
+        .help("minimum number of valid loads in each hour, otherwise nan")
+        .long("min_valid")
+        .alias("min-valid")
+        .num_args(1)
+        .value_parser(value_parser!(usize))
+        .default_value("1");
+
+    let arg_nan_propagate = Arg::new("nan_propagate")
+        .help("give nan for the hours with any nan load, instead of ignoring them")
+        .long("nan_propagate")
+        .alias("nan-propagate")
+        .num_args(0)
+        .required(false);
+
+    let arg_time_weighted = Arg::new("time_weighted")
+        .help("weight the mean of each hour by the time of each load, for irregular logging")
+        .long("time_weighted")
+        .alias("time-weighted")
+        .num_args(0)
+        .conflicts_with("agg")
+        .required(false);
+
+    let arg_step = Arg::new("step")
+        .help("logging interval in minutes capping the time weights, otherwise the smallest step")
+        .long("step")
+        .num_args(1)
+        .value_parser(value_parser!(i64).range(1..))
+        .requires("time_weighted")
+        .required(false);
+
+    let arg_quiet = Arg::new("quiet")
+        .help("do not print progress messages")
+        .short('q')
+        .long("quiet")
+        .num_args(0)
+        .required(false);
+
     let cli_args = Command::new("Flintec_downsample")
         .version(VERSION.unwrap_or("unknown"))
         .author("Luca Peruzzo")
         .about("cli app to downsample the load time series")
         .arg(arg_csvin)
         .arg(arg_csvout)
+        .arg(arg_agg)
+        .arg(arg_min_valid)
+        .arg(arg_nan_propagate)
+        .arg(arg_time_weighted)
+        .arg(arg_step)
+        .arg(arg_quiet)
         .get_matches();
 
     let csvin: PathBuf = cli_args
@@ -40,7 +91,26 @@ pub fn parse_cli() -> (PathBuf, PathBuf) {
         None => csvin.with_file_name("hourly.csv"),
     };
 
-    println!("read from {:?} and save to {:?}", csvin, csvout);
+    let agg: Aggregator = cli_args
+        .get_one::<String>("agg")
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    let nan: NanPolicy = if cli_args.get_flag("nan_propagate") {
+        NanPolicy::Propagate
+    } else {
+        let min_valid = *cli_args.get_one::<usize>("min_valid").unwrap();
+        NanPolicy::Ignore { min_valid }
+    };
+
+    let time_weighted: bool = cli_args.get_flag("time_weighted");
+    let step: Option<i64> = cli_args.get_one::<i64>("step").copied();
+    let quiet: bool = cli_args.get_flag("quiet");
+
+    if !quiet {
+        eprintln!("read from {:?} and save to {:?}", csvin, csvout);
+    }
 
-    return (csvin, csvout);
+    (csvin, csvout, agg, nan, time_weighted, step, quiet)
 }
@@ -1,6 +1,8 @@
 use chrono::prelude::*;
 use rayon::prelude::*;
+use std::borrow::Cow;
 use std::cmp::PartialOrd;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
@@ -23,26 +25,113 @@ pub fn suitable_xfmt(d: chrono::Duration) -> &'static str {
 
 
 /// Read a list of bad datetimes to skip, always from RFC 3339 - ISO 8601 format.
-pub fn read_bad_datetimes<P>(fin: P) -> Vec<DateTime<FixedOffset>>
+/// Empty lines and lines starting with # are skipped,
+/// e.g., comments with the reason for the exclusion.
+/// All the lines that cannot be parsed are collected and returned together in the error,
+/// so that the whole file can be fixed at once.
+pub fn read_bad_datetimes<P>(fin: P) -> Result<Vec<DateTime<FixedOffset>>, BadDatetimeParseError>
 where
     P: AsRef<Path>,
 {
-    let file = File::open(fin).unwrap();
+    let file = File::open(fin)?;
     let buf = BufReader::new(file);
     let mut bad_datetimes: Vec<DateTime<FixedOffset>> = Vec::new();
+    let mut parse_errors: Vec<(usize, String)> = Vec::new();
+    for (n, l) in buf.lines().enumerate() {
+        let l_unwrap = match l {
+            Ok(l_ok) => l_ok,
+            Err(l_err) => {
+                eprintln!("Err, could not read/unwrap line {}", l_err);
+                continue;
+            }
+        };
+        let l_trim = l_unwrap.trim();
+        if l_trim.is_empty() || l_trim.starts_with('#') {
+            continue;
+        }
+        match DateTime::parse_from_rfc3339(l_trim) {
+            Ok(dt) => bad_datetimes.push(dt),
+            Err(_) => parse_errors.push((n + 1, l_unwrap)),
+        }
+    }
+    if !parse_errors.is_empty() {
+        return Err(BadDatetimeParseError::Lines(parse_errors));
+    }
+    Ok(bad_datetimes)
+}
+
+/// An interval as start and end datetimes, e.g., from read_bad_intervals.
+pub type DatetimeInterval = (DateTime<FixedOffset>, DateTime<FixedOffset>);
+
+/// Read a list of bad intervals, one per line as start,end datetimes,
+/// always in RFC 3339 - ISO 8601 format.
+/// Empty lines and lines starting with # are skipped, as for read_bad_datetimes,
+/// and all the lines that cannot be parsed are returned together in the error.
+pub fn read_bad_intervals<P>(fin: P) -> Result<Vec<DatetimeInterval>, BadDatetimeParseError>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(fin)?;
+    let buf = BufReader::new(file);
+    let mut bad_intervals: Vec<DatetimeInterval> = Vec::new();
+    let mut parse_errors: Vec<(usize, String)> = Vec::new();
+    for (n, l) in buf.lines().enumerate() {
+        let l_unwrap = match l {
+            Ok(l_ok) => l_ok,
+            Err(l_err) => {
+                eprintln!("Err, could not read/unwrap line {}", l_err);
+                continue;
+            }
+        };
+        let l_trim = l_unwrap.trim();
+        if l_trim.is_empty() || l_trim.starts_with('#') {
+            continue;
+        }
+        let parsed = l_trim.split_once(',').and_then(|(s, e)| {
+            let s = DateTime::parse_from_rfc3339(s.trim()).ok()?;
+            let e = DateTime::parse_from_rfc3339(e.trim()).ok()?;
+            Some((s, e))
+        });
+        match parsed {
+            Some(interval) => bad_intervals.push(interval),
+            None => parse_errors.push((n + 1, l_unwrap)),
+        }
+    }
+    if !parse_errors.is_empty() {
+        return Err(BadDatetimeParseError::Lines(parse_errors));
+    }
+    Ok(bad_intervals)
+}
+
+/// Read a mask with one boolean per line, true (or 1) for good and false (or 0) for bad samples.
+/// Error if the file cannot be opened or a line is not a valid mask value.
+pub fn read_mask<P>(fin: P) -> Result<Vec<bool>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(fin)?;
+    let buf = BufReader::new(file);
+    let mut mask: Vec<bool> = Vec::new();
     for l in buf.lines() {
         let l_unwrap = match l {
             Ok(l_ok) => l_ok,
             Err(l_err) => {
-                println!("Err, could not read/unwrap line {}", l_err);
+                eprintln!("Err, could not read/unwrap line {}", l_err);
                 continue;
             }
         };
-        bad_datetimes.push(DateTime::parse_from_rfc3339(&l_unwrap).unwrap());
+        let m = match l_unwrap.trim() {
+            "true" | "1" => true,
+            "false" | "0" => false,
+            other => return Err(format!("invalid mask value: {}", other).into()),
+        };
+        mask.push(m);
     }
-    return bad_datetimes;
+    Ok(mask)
 }
 
+/// The minimum and maximum of the iterator, panic if it is empty.
+/// For floats with NANs, see min_and_max_finite.
 pub fn min_and_max<'a, I, T>(mut s: I) -> (T, T)
 where
     I: Iterator<Item = &'a T>,
@@ -62,6 +151,49 @@ where
     return (min.clone(), max.clone());
 }
 
+/// The minimum and maximum of the finite values, each with its (first) index,
+/// as ((imin, min), (imax, max)). None if there are no finite values, e.g., empty or all NAN.
+/// Unlike min_and_max, it does not panic and it is not confused by the NANs.
+pub fn min_and_max_finite(v: &[f64]) -> Option<((usize, f64), (usize, f64))> {
+    let mut finite = v.iter().copied().enumerate().filter(|(_, f)| f.is_finite());
+    let first = finite.next()?;
+    let min_max = finite.fold((first, first), |(min, max), e| {
+        (
+            if e.1 < min.1 { e } else { min },
+            if e.1 > max.1 { e } else { max },
+        )
+    });
+    Some(min_max)
+}
+
+/// Histogram of the finite values with the given number of equal bins from their min to max,
+/// as (bin_low, bin_high, count); each bin includes its low edge, the last one also the max.
+/// A single bin if all the finite values are equal, empty if there are none or bins is 0.
+pub fn histogram(v: &[f64], bins: usize) -> Vec<(f64, f64, usize)> {
+    let (min, max) = match min_and_max_finite(v) {
+        Some(((_, min), (_, max))) if bins > 0 => (min, max),
+        _ => return Vec::new(),
+    };
+    let finite = v.iter().filter(|f| f.is_finite());
+    if min == max {
+        return vec![(min, max, finite.count())];
+    }
+    let width = (max - min) / bins as f64;
+    let mut counts = vec![0usize; bins];
+    for f in finite {
+        let b = ((f - min) / width).floor() as usize;
+        counts[std::cmp::min(b, bins - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(b, c)| {
+            let high = if b == bins - 1 { max } else { min + (b + 1) as f64 * width };
+            (min + b as f64 * width, high, c)
+        })
+        .collect()
+}
+
 pub fn make_window(w_central: f64, w_side: f64, side: usize) -> Vec<f64> {
     let w_step = (w_central - w_side) / (side as f64);
     let up = (0..side + 1).map(|n| w_side + (n as f64 * w_step));
@@ -70,6 +202,39 @@ pub fn make_window(w_central: f64, w_side: f64, side: usize) -> Vec<f64> {
     updown
 }
 
+/// Gaussian window for mavg, exp(-x^2 / (2 sigma^2)) for x in -side..=side samples.
+/// The length is 2 * side + 1, odd with the peak at the central element.
+pub fn make_window_gaussian(sigma: f64, side: usize) -> Vec<f64> {
+    assert!(sigma > 0., "the standard deviation of the window must be positive");
+    (0..2 * side + 1)
+        .map(|n| {
+            let x = n as f64 - side as f64;
+            (-x * x / (2. * sigma * sigma)).exp()
+        })
+        .collect()
+}
+
+/// Asymmetric window for mavg, with linear ramps from w_left to w_central over left samples,
+/// and from w_central to w_right over right samples, e.g., right = 0 for causal smoothing.
+/// As mavg takes the middle element as the center, the shorter side is padded with zero weights;
+/// the length is 2 * max(left, right) + 1, always odd with w_central at the middle.
+/// Note that the padded values still count for max_missing_v when missing (e.g., at the ends).
+pub fn make_window_asymmetric(
+    w_central: f64,
+    w_left: f64,
+    w_right: f64,
+    left: usize,
+    right: usize,
+) -> Vec<f64> {
+    let side = std::cmp::max(left, right);
+    let mut w: Vec<f64> = vec![0.; side - left];
+    w.extend((0..left).map(|n| w_left + n as f64 * (w_central - w_left) / left as f64));
+    w.push(w_central);
+    w.extend((1..right + 1).map(|n| w_central + n as f64 * (w_right - w_central) / right as f64));
+    w.extend(vec![0.; side - right]);
+    w
+}
+
 // Flexible Weighted Moving Average implementation with parameters to handle the maximum missing information.
 /// Roll the weighted moving window w over the data v,
 /// also filling the NAN values with the weighted average when possible:
@@ -77,6 +242,29 @@ pub fn make_window(w_central: f64, w_side: f64, side: usize) -> Vec<f64> {
 /// 2) the window weight associated with the present data is sufficient, i.e.,
 ///     the percentage of missing weight is < than max_missing_wpct.
 pub fn mavg(v: &[f64], w: &[f64], max_missing_v: usize, max_missing_wpct: f64) -> Vec<f64> {
+    mavg_with_edges(v, w, max_missing_v, max_missing_wpct, EdgeMode::Nan)
+}
+
+/// How mavg handles the window when it hangs off the ends of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeMode {
+    /// The values off the ends count as missing values, as in mavg.
+    Nan,
+    /// The window is truncated at the ends and re-normalized by the remaining weights.
+    Shrink,
+    /// The data are mirrored at the ends, e.g., v[-1] = v[1].
+    Reflect,
+}
+
+/// As mavg, with the given handling of the window at the ends of the data,
+/// e.g., to keep the ends of short time series.
+pub fn mavg_with_edges(
+    v: &[f64],
+    w: &[f64],
+    max_missing_v: usize,
+    max_missing_wpct: f64,
+    edge: EdgeMode,
+) -> Vec<f64> {
     let len_v: i32 = v.len() as i32;
     let len_w: i32 = w.len() as i32;
     assert!(
@@ -88,44 +276,323 @@ pub fn mavg(v: &[f64], w: &[f64], max_missing_v: usize, max_missing_wpct: f64) -
         "the moving average window has an even number of elements; \
         it should be odd to have a central element"
     );
-    let side: i32 = (len_w - 1) / 2;
     let sum_all_w: f64 = w.iter().sum();
     let max_missing_w: f64 = sum_all_w / 100. * max_missing_wpct;
     let mut vout: Vec<f64> = Vec::with_capacity(len_v as usize);
     for i in 0..len_v {
+        vout.push(mavg_element(v, w, i, max_missing_v, max_missing_w, edge));
+    }
+    vout
+}
+
+// The weighted average of mavg for the element i of v, shared with mavg_parallel.
+fn mavg_element(
+    v: &[f64],
+    w: &[f64],
+    i: i32,
+    max_missing_v: usize,
+    max_missing_w: f64,
+    edge: EdgeMode,
+) -> f64 {
+    let len_v: i32 = v.len() as i32;
+    let side: i32 = (w.len() as i32 - 1) / 2;
+    let vl = i - side;
+    let vr = i + side + 1;
+    let vw = (vl..vr).zip(w.iter()).filter_map(|(j, we)| {
+        // the window is shorter than v, thus the mirrored index is always within v
+        let j = match edge {
+            EdgeMode::Reflect if j < 0 => -j,
+            EdgeMode::Reflect if j >= len_v => 2 * (len_v - 1) - j,
+            _ => j,
+        };
+        if (j < 0) || (j >= len_v) {
+            if edge == EdgeMode::Shrink {
+                return None;
+            }
+            Some((f64::NAN, *we))
+        } else {
+            Some((v[j as usize], *we))
+        }
+    });
+    weighted_mean_missing(vw, max_missing_v, max_missing_w)
+}
+
+// The weighted average of the (value, weight) pairs, with NAN for the missing values,
+// shared by mavg_element and mavg_causal; NAN if too many values are missing.
+fn weighted_mean_missing(
+    vw: impl Iterator<Item = (f64, f64)>,
+    max_missing_v: usize,
+    max_missing_w: f64,
+) -> f64 {
+    let mut missing_v = 0;
+    let mut missing_w = 0.;
+    let mut sum_ve_we = 0.;
+    let mut sum_we = 0.;
+    for (ve, we) in vw {
+        if ve.is_nan() {
+            missing_v += 1;
+            missing_w += we;
+        } else {
+            sum_ve_we += ve * we;
+            sum_we += we;
+        }
+        if (missing_v > max_missing_v) || (missing_w > max_missing_w) {
+            // println!(
+            //     "setting to NAN; {} missing data with limit {}, {} missing window weight with limit {}",
+            //     missing_v, max_missing_v, missing_w, max_missing_w,
+            // );
+            return f64::NAN;
+        }
+    }
+    sum_ve_we / sum_we
+}
+
+/// Causal (trailing) version of mavg, which only uses the current and past values,
+/// e.g., at the live edge of the logging, where the future values of a centered window are missing.
+/// The last element of the window w weights the current value, the first one the oldest;
+/// w can have any length, e.g., the first half of a make_window including the central element.
+/// The missing values are handled as in mavg, the values before the start count as missing.
+/// The trade-off is the phase lag: the smoothed series is delayed by the weighted mean age
+/// of the window, e.g., side / 2 samples for a flat window of side + 1 samples,
+/// while the centered mavg has no lag but needs side future values.
+pub fn mavg_causal(v: &[f64], w: &[f64], max_missing_v: usize, max_missing_wpct: f64) -> Vec<f64> {
+    assert!(!w.is_empty(), "the moving average window is empty");
+    let sum_all_w: f64 = w.iter().sum();
+    let max_missing_w: f64 = sum_all_w / 100. * max_missing_wpct;
+    let len_w = w.len();
+    (0..v.len())
+        .map(|i| {
+            // w[len_w - 1] is for v[i], thus w[k] is for v[i + 1 + k - len_w]
+            let vw = w.iter().enumerate().map(|(k, we)| {
+                let ve = match (i + 1 + k).checked_sub(len_w) {
+                    Some(j) => v[j],
+                    None => f64::NAN,
+                };
+                (ve, *we)
+            });
+            weighted_mean_missing(vw, max_missing_v, max_missing_w)
+        })
+        .collect()
+}
+
+/// Parallel version of mavg, with the same handling of the missing values,
+/// and thus the same output, for long time series.
+pub fn mavg_parallel(
+    v: &[f64],
+    w: &[f64],
+    max_missing_v: usize,
+    max_missing_wpct: f64,
+) -> Vec<f64> {
+    let len_v: i32 = v.len() as i32;
+    let len_w: i32 = w.len() as i32;
+    assert!(
+        len_w < len_v,
+        "length of moving average window > length of vector"
+    );
+    assert!(
+        len_w % 2 == 1,
+        "the moving average window has an even number of elements; \
+        it should be odd to have a central element"
+    );
+    let sum_all_w: f64 = w.iter().sum();
+    let max_missing_w: f64 = sum_all_w / 100. * max_missing_wpct;
+    (0..len_v)
+        .into_par_iter()
+        .map(|i| mavg_element(v, w, i, max_missing_v, max_missing_w, EdgeMode::Nan))
+        .collect()
+}
+
+/// Exponential weighted moving average, s[i] = alpha * v[i] + (1 - alpha) * s[i - 1],
+/// starting from the first finite value, with alpha in (0, 1].
+/// A NAN carries the previous smoothed value forward, the leading NANs stay NAN.
+pub fn ewma(v: &[f64], alpha: f64) -> Vec<f64> {
+    assert!(
+        (alpha > 0.) & (alpha <= 1.),
+        "the ewma smoothing factor must be in (0, 1]"
+    );
+    let mut smooth = f64::NAN;
+    v.iter()
+        .map(|e| {
+            if !e.is_nan() {
+                smooth = if smooth.is_nan() {
+                    *e
+                } else {
+                    alpha * e + (1. - alpha) * smooth
+                };
+            }
+            smooth
+        })
+        .collect()
+}
+
+/// As ewma, but for irregular samples, with the time constant tau:
+/// each value is weighted by the time elapsed since the last finite value,
+/// alpha = 1 - exp(-dt / tau), i.e., equivalent to ewma for a regular time step.
+pub fn ewma_time(time: &[DateTime<FixedOffset>], v: &[f64], tau: chrono::Duration) -> Vec<f64> {
+    assert_eq!(time.len(), v.len(), "time and values have different lengths");
+    let tau_s = tau.num_milliseconds() as f64 / 1000.;
+    assert!(tau_s > 0., "the ewma time constant must be positive");
+    let mut smooth = f64::NAN;
+    let mut last_t: Option<DateTime<FixedOffset>> = None;
+    time.iter()
+        .zip(v.iter())
+        .map(|(t, e)| {
+            if e.is_nan() {
+                return smooth;
+            }
+            smooth = match last_t {
+                None => *e,
+                Some(lt) => {
+                    let dt_s = (*t - lt).num_milliseconds() as f64 / 1000.;
+                    let alpha = 1. - (-dt_s / tau_s).exp();
+                    alpha * e + (1. - alpha) * smooth
+                }
+            };
+            last_t = Some(*t);
+            smooth
+        })
+        .collect()
+}
+
+/// Trailing rolling sum, i.e., the sum of the `window` values ending at each element.
+/// NANs are skipped, but at least `min_count` finite values are required, otherwise NAN.
+/// The first `window - 1` elements do not have a full window and are NAN.
+pub fn rolling_sum(v: &[f64], window: usize, min_count: usize) -> Vec<f64> {
+    assert!(window > 0, "rolling_sum: the window should not be empty");
+    let mut vout: Vec<f64> = vec![f64::NAN; v.len()];
+    if window > v.len() {
+        return vout;
+    }
+    v.windows(window)
+        .zip(vout[window - 1..].iter_mut())
+        .for_each(|(vw, vout_e)| {
+            let (count, sum) = vw
+                .iter()
+                .filter(|e| e.is_finite())
+                .fold((0usize, 0f64), |(c, s), e| (c + 1, s + e));
+            if count >= min_count {
+                *vout_e = sum;
+            }
+        });
+    vout
+}
+
+/// Rolling median to remove isolated spikes, e.g., before the moving average.
+/// As for mavg, the window is centered and should have an odd number of elements.
+/// NANs are skipped, but at least half of the window should be finite, otherwise NAN.
+/// At the edges, the window is shrunk to the available elements instead of padding.
+pub fn rolling_median(v: &[f64], window: usize) -> Vec<f64> {
+    assert!(
+        window % 2 == 1,
+        "the rolling median window has an even number of elements; \
+        it should be odd to have a central element"
+    );
+    let side = (window - 1) / 2;
+    let mut vout: Vec<f64> = Vec::with_capacity(v.len());
+    for i in 0..v.len() {
+        let vw = &v[i.saturating_sub(side)..std::cmp::min(i + side + 1, v.len())];
+        let mut finite: Vec<f64> = vw.iter().filter(|e| e.is_finite()).copied().collect();
+        if 2 * finite.len() < vw.len() {
+            vout.push(f64::NAN);
+            continue;
+        }
+        vout.push(median_in_place(&mut finite));
+    }
+    vout
+}
+
+// Median of a non-empty vector of finite values, sorting it in place.
+pub(crate) fn median_in_place(v: &mut [f64]) -> f64 {
+    v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = v.len() / 2;
+    if v.len() % 2 == 1 {
+        v[mid]
+    } else {
+        (v[mid - 1] + v[mid]) / 2.
+    }
+}
+
+/// Online version of mavg for live data, e.g., to smooth the readings while logging.
+/// The values are pushed one at a time, and each push returns the moving average
+/// of the value at the center of the window, i.e., with a delay of side values,
+/// or None while the first side values are pushed.
+/// The missing values (NAN and before the first value) are handled as in mavg,
+/// thus the results match mavg, except for the last side values that are not returned.
+#[derive(Debug, Clone)]
+pub struct RollingMean {
+    w: Vec<f64>,
+    window: VecDeque<f64>,
+    max_missing_v: usize,
+    max_missing_w: f64,
+    pushed: usize,
+}
+
+impl RollingMean {
+    /// Initiate the rolling mean with the window, e.g., from make_window,
+    /// and the missing values limits of mavg.
+    pub fn new(w: Vec<f64>, max_missing_v: usize, max_missing_wpct: f64) -> RollingMean {
+        assert!(
+            w.len() % 2 == 1,
+            "the moving average window has an even number of elements; \
+            it should be odd to have a central element"
+        );
+        let sum_all_w: f64 = w.iter().sum();
+        let window: VecDeque<f64> = vec![f64::NAN; w.len()].into();
+        RollingMean {
+            w,
+            window,
+            max_missing_v,
+            max_missing_w: sum_all_w / 100. * max_missing_wpct,
+            pushed: 0usize,
+        }
+    }
+
+    /// Push a new value and return the moving average of the central value, if any.
+    pub fn push(&mut self, value: f64) -> Option<f64> {
+        self.window.pop_front();
+        self.window.push_back(value);
+        self.pushed += 1;
+        if self.pushed <= (self.w.len() - 1) / 2 {
+            return None;
+        }
         let mut missing_v = 0;
         let mut missing_w = 0.;
         let mut sum_ve_we = 0.;
         let mut sum_we = 0.;
-        let mut ve: f64;
-        let vl = i - side;
-        let vr = i + side + 1;
-        for (j, we) in (vl..vr).zip(w.iter()) {
-            if (j < 0) || (j >= len_v) {
+        for (ve, we) in self.window.iter().zip(self.w.iter()) {
+            if ve.is_nan() {
                 missing_v += 1;
                 missing_w += we;
             } else {
-                ve = v[j as usize];
-                if ve.is_nan() {
-                    missing_v += 1;
-                    missing_w += we;
-                } else {
-                    sum_ve_we += ve * we;
-                    sum_we += we;
-                }
+                sum_ve_we += ve * we;
+                sum_we += we;
             }
-            if (missing_v > max_missing_v) || (missing_w > max_missing_w) {
-                sum_ve_we = f64::NAN;
-                // println!(
-                //     "setting to NAN; {} missing data with limit {}, {} missing window weight with limit {}",
-                //     missing_v, max_missing_v, missing_w, max_missing_w,
-                // );
-                break;
+            if (missing_v > self.max_missing_v) || (missing_w > self.max_missing_w) {
+                return Some(f64::NAN);
             }
         }
-        vout.push(sum_ve_we / sum_we);
+        Some(sum_ve_we / sum_we)
+    }
+}
+
+/// Minimum work, i.e., length of the data times length of the window,
+/// for mavg_auto to choose mavg_parallel over mavg.
+/// From the bench_mavg_crossover sweep (1E+3 to 1E+5 values, windows of 21 and 361),
+/// the scalar mavg takes about 3 ns per product at any size, while mavg_parallel adds
+/// about 20 us of rayon overhead and is about 5% slower on a single thread.
+/// With two threads it breaks even at about 1.3E+4 products; the threshold keeps a margin
+/// of about 4 times, i.e., about 150 us of scalar work, for the scheduling noise.
+pub const MAVG_PARALLEL_MIN_WORK: usize = 50_000;
+
+/// As mavg, choosing the scalar or the parallel implementation by the size of the work,
+/// see MAVG_PARALLEL_MIN_WORK, and the scalar one on a single thread.
+/// The output is the same for both.
+pub fn mavg_auto(v: &[f64], w: &[f64], max_missing_v: usize, max_missing_wpct: f64) -> Vec<f64> {
+    if (v.len() * w.len() < MAVG_PARALLEL_MIN_WORK) | (rayon::current_num_threads() < 2) {
+        mavg(v, w, max_missing_v, max_missing_wpct)
+    } else {
+        mavg_parallel(v, w, max_missing_v, max_missing_wpct)
     }
-    vout
 }
 
 // Weighted Moving Average implementation for long windows and
@@ -134,6 +601,8 @@ pub fn mavg(v: &[f64], w: &[f64], max_missing_v: usize, max_missing_wpct: f64) -
 // that splits the multiplication step from the successive sum.
 // This allows SIMD parallelism, but requires second loop over the window for the sum.
 // The SIMD optimization, in addition to the multi-threading, has been confirmed by the assembly.
+// Note, there is no handling of the missing values: a NAN makes NAN all the windows it is in,
+// and the first and last side values are NAN; use mavg_parallel for data with missing values.
 pub fn mavg_parallel_simd(v: &[f64], w: &[f64]) -> Vec<f64> {
     let len_v: usize = v.len();
     let len_w: usize = w.len();
@@ -168,6 +637,7 @@ pub fn mavg_parallel_simd(v: &[f64], w: &[f64]) -> Vec<f64> {
 // This is a parallel implementation of the moving average that
 // allows the sum of the weighted loads to be directly executed,
 // i.e., pair-wise multiplication proceed together with the sum.
+// As mavg_parallel_simd, there is no handling of the missing values (NAN-unsafe).
 pub fn mavg_parallel_fold(v: &[f64], w: &[f64]) -> Vec<f64> {
     let len_v: usize = v.len();
     let len_w: usize = w.len();
@@ -214,6 +684,41 @@ pub fn find_anomalies(
     min_window_data: usize,
     max_iqr: f64,
 ) -> (Vec<usize>, Vec<f64>) {
+    let reports = find_anomalies_detailed(v, window_width, min_window_data, max_iqr);
+    let mut anomalies_index: Vec<usize> = reports.iter().flat_map(|r| r.indices.clone()).collect();
+    // Anomalous windows may give duplicates, keep only unique indices:
+    // first, order the indices so that multiple duplicates will be consecutive,
+    // then deduplicate more quickly and in-place.
+    anomalies_index.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (anomalies_index_dedup, _) = anomalies_index.partition_dedup_by(|a, b| a == b);
+    let anomalies_index_dedup = anomalies_index_dedup.to_vec();
+    let mut anomalies_load: Vec<f64> = Vec::new();
+    for i in anomalies_index_dedup.iter() {
+        anomalies_load.push(v[*i]);
+    }
+    return (anomalies_index_dedup, anomalies_load);
+}
+
+/// An anomalous window found by find_anomalies_detailed,
+/// with the quartiles and IQR that flagged it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyReport {
+    pub window_start: usize,
+    pub indices: std::ops::Range<usize>,
+    pub ql: f64,
+    pub qu: f64,
+    pub iqr: f64,
+}
+
+// As find_anomalies, but return a report for each anomalous window,
+// in the order of the windows, so that it is clear why a period was flagged.
+// Overlapping windows are reported separately, thus the indices are not unique.
+pub fn find_anomalies_detailed(
+    v: &[f64],
+    window_width: usize,
+    min_window_data: usize,
+    max_iqr: f64,
+) -> Vec<AnomalyReport> {
     // Initial length checks for consistent lengths
     pub const MIN_DATA_IQR: usize = 6usize;
     if min_window_data < MIN_DATA_IQR {
@@ -225,30 +730,150 @@ pub fn find_anomalies(
     if min_window_data > window_width {
         panic!("find_anomalies: impossible to proceed as window_width < min_window_data");
     }
-    let mut anomalies_index: Vec<usize> = Vec::new();
-    let indices: Vec<usize> = (0..v.len()).collect();
-    for (wl, wi) in v.windows(window_width).zip(indices.windows(window_width)) {
-        let (_ql, _qu, iqr) = match calculate_iqr(wl, min_window_data) {
+    let mut reports: Vec<AnomalyReport> = Vec::new();
+    for (window_start, wl) in v.windows(window_width).enumerate() {
+        let (ql, qu, iqr) = match calculate_iqr(wl, min_window_data) {
             Ok(res) => res,
             Err(_e) => {
                 continue;
             }
         };
         if iqr > max_iqr {
-            anomalies_index.append(&mut wi.to_owned());
+            reports.push(AnomalyReport {
+                window_start,
+                indices: window_start..window_start + window_width,
+                ql,
+                qu,
+                iqr,
+            });
         }
     }
-    // Anomalous windows may give duplicates, keep only unique indices:
-    // first, order the indices so that multiple duplicates will be consecutive,
-    // then deduplicate more quickly and in-place.
-    anomalies_index.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let (anomalies_index_dedup, _) = anomalies_index.partition_dedup_by(|a, b| a == b);
-    let anomalies_index_dedup = anomalies_index_dedup.to_vec();
-    let mut anomalies_load: Vec<f64> = Vec::new();
-    for i in anomalies_index_dedup.iter() {
-        anomalies_load.push(v[*i]);
+    reports
+}
+
+/// First difference of the vector, d[i] = v[i + 1] - v[i], one element shorter than v.
+/// A NAN on either side gives a NAN difference.
+pub fn first_difference(v: &[f64]) -> Vec<f64> {
+    v.windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+// Anomaly detection as find_anomalies, but on the first difference of `v`.
+// This catches abnormal rates of change (fast jumps) even when the values stay in range.
+// Each anomalous difference d[i] is mapped back to both the samples it comes from, i and i + 1.
+//
+// Return unique values of the indices and loads of `v` that fell in an anomalous window.
+pub fn find_anomalies_on_derivative(
+    v: &[f64],
+    window_width: usize,
+    min_window_data: usize,
+    max_iqr: f64,
+) -> (Vec<usize>, Vec<f64>) {
+    let d = first_difference(v);
+    let (d_index, _) = find_anomalies(&d, window_width, min_window_data, max_iqr);
+    let mut anomalies_index: Vec<usize> = d_index.iter().flat_map(|i| [*i, *i + 1]).collect();
+    // d_index is sorted, thus the mapped indices only have consecutive duplicates
+    anomalies_index.dedup();
+    let anomalies_load: Vec<f64> = anomalies_index.iter().map(|i| v[*i]).collect();
+    (anomalies_index, anomalies_load)
+}
+
+// Step detection, e.g., the platform bumped to a new baseline.
+// At each index i, compare the mean of the `window` values before i with the mean of the `window`
+// values from i, requiring at least half of each window to be finite (see step_magnitude).
+// The consecutive indices with a difference > `min_step` (in absolute value) belong to one step,
+// only the index of the largest difference is kept; the step is then between i - 1 and i.
+// Unlike find_anomalies_on_derivative, this isolates permanent shifts from transient spikes.
+//
+// Return the indices of the first values after the steps, sorted.
+pub fn find_steps(v: &[f64], window: usize, min_step: f64) -> Vec<usize> {
+    if (window == 0) || (v.len() < 2 * window) {
+        return Vec::new();
     }
-    return (anomalies_index_dedup, anomalies_load);
+    let candidates: Vec<usize> = (window..=v.len() - window)
+        .filter(|i| step_magnitude(v, *i, window).abs() > min_step)
+        .collect();
+    index_runs(&candidates)
+        .into_iter()
+        .map(|(s, e)| {
+            (s..=e)
+                .map(|i| (i, step_magnitude(v, i, window).abs()))
+                .fold((s, f64::NEG_INFINITY), |m, c| if c.1 > m.1 { c } else { m })
+                .0
+        })
+        .collect()
+}
+
+/// Signed magnitude of the step at index i, the mean of the window from i
+/// minus the mean of the window before i; the offset to subtract from i on to remove the step.
+/// NAN if either window has less than half finite values or is cut by the ends of v.
+pub fn step_magnitude(v: &[f64], i: usize, window: usize) -> f64 {
+    if (window == 0) || (i < window) || (i + window > v.len()) {
+        return f64::NAN;
+    }
+    let min_valid = std::cmp::max(window / 2, 1);
+    mean_ignore_nan(&v[i..i + window], min_valid) - mean_ignore_nan(&v[i - window..i], min_valid)
+}
+
+// Robust outlier detection with the median absolute deviation (MAD).
+// Run a centered rolling window of width `window` (odd, shrinking at the edges) over `v`,
+// and flag the central value if its deviation from the window median is > `threshold` * MAD.
+// As rolling_median, skip the windows with less than half finite values;
+// also skip the windows with zero MAD (e.g., constant values), where any change would be flagged.
+//
+// Return the indices of the outliers, sorted.
+pub fn find_outliers_mad(v: &[f64], window: usize, threshold: f64) -> Vec<usize> {
+    assert!(
+        window % 2 == 1,
+        "the MAD window has an even number of elements; \
+        it should be odd to have a central element"
+    );
+    let side = (window - 1) / 2;
+    let mut outliers: Vec<usize> = Vec::new();
+    for i in 0..v.len() {
+        if !v[i].is_finite() {
+            continue;
+        }
+        match window_median_mad(v, i, side) {
+            Some((median, mad)) if (mad > 0.) & ((v[i] - median).abs() > threshold * mad) => {
+                outliers.push(i)
+            }
+            _ => {}
+        }
+    }
+    outliers
+}
+
+// Median and MAD of the finite values in the centered window of v[i], shrinking at the edges,
+// None if less than half of the window is finite.
+fn window_median_mad(v: &[f64], i: usize, side: usize) -> Option<(f64, f64)> {
+    let vw = &v[i.saturating_sub(side)..std::cmp::min(i + side + 1, v.len())];
+    let mut finite: Vec<f64> = vw.iter().filter(|e| e.is_finite()).copied().collect();
+    if 2 * finite.len() < vw.len() {
+        return None;
+    }
+    let median = median_in_place(&mut finite);
+    let mut deviations: Vec<f64> = finite.iter().map(|e| (e - median).abs()).collect();
+    let mad = median_in_place(&mut deviations);
+    Some((median, mad))
+}
+
+/// Scale of the MAD to estimate the standard deviation of normally distributed values.
+pub const MAD_TO_SIGMA: f64 = 1.4826;
+
+/// Hampel filter, replace the outliers with the median of their window instead of NAN,
+/// preserving the finite values, e.g., between the outlier masking and the moving average.
+/// The outliers are found as in find_outliers_mad, with the threshold of n_sigmas
+/// standard deviations estimated from the MAD, see MAD_TO_SIGMA.
+/// Return the filtered values and the indices of the replaced ones, sorted.
+pub fn hampel(v: &[f64], window: usize, n_sigmas: f64) -> (Vec<f64>, Vec<usize>) {
+    let outliers = find_outliers_mad(v, window, n_sigmas * MAD_TO_SIGMA);
+    let side = (window - 1) / 2;
+    let mut filtered = v.to_vec();
+    for i in outliers.iter() {
+        // the windows of the outliers have a median, see find_outliers_mad
+        filtered[*i] = window_median_mad(v, *i, side).unwrap().0;
+    }
+    (filtered, outliers)
 }
 
 // Calculate the lower and upper quartiles
@@ -289,6 +914,16 @@ pub fn calculate_iqr(s: &[f64], min_len: usize) -> Result<(f64, f64, f64), LenEr
     return Ok((ql, qu, iqr));
 }
 
+/// Tukey's constant for the outlier fences, see iqr_fences.
+pub const TUKEY_K: f64 = 1.5;
+
+/// Tukey's outlier fences (ql - k * iqr, qu + k * iqr), with the quartiles of calculate_iqr.
+/// The usual choice is k = TUKEY_K (1.5), and 3 for the far outliers.
+pub fn iqr_fences(s: &[f64], min_len: usize, k: f64) -> Result<(f64, f64), LenErr> {
+    let (ql, qu, iqr) = calculate_iqr(s, min_len)?;
+    Ok((ql - k * iqr, qu + k * iqr))
+}
+
 pub fn mean_or_nan(v: &Vec<f64>) -> f64 {
     
     let mut contains_nan = false;
@@ -309,6 +944,94 @@ pub fn mean_or_nan(v: &Vec<f64>) -> f64 {
     mean
 }
 
+/// Mean of the finite values, ignoring the NANs instead of returning NAN as mean_or_nan.
+/// NAN if there are fewer than min_valid finite values (at least one),
+/// e.g., to flag an hour with only 1 of 60 valid samples.
+pub fn mean_ignore_nan(v: &[f64], min_valid: usize) -> f64 {
+    let (sum, count) = v
+        .iter()
+        .filter(|f| !f.is_nan())
+        .fold((0., 0usize), |(s, c), f| (s + f, c + 1));
+    if count < min_valid.max(1) {
+        f64::NAN
+    } else {
+        sum / count as f64
+    }
+}
+
+
+/// Largest-Triangle-Three-Buckets downsampling, return the indices of the (about) threshold
+/// points that best preserve the visual shape of the line, always including the first and last.
+/// The points are expected to be finite and ordered by x.
+/// All the indices are returned if there are no more points than the threshold.
+pub fn lttb_indices(x: &[f64], y: &[f64], threshold: usize) -> Vec<usize> {
+    let n = x.len();
+    if (threshold >= n) | (n < 3) {
+        return (0..n).collect();
+    }
+    if threshold < 3 {
+        return vec![0, n - 1];
+    }
+    let every = (n - 2) as f64 / (threshold - 2) as f64;
+    let mut sampled: Vec<usize> = Vec::with_capacity(threshold);
+    let mut a = 0usize;
+    sampled.push(a);
+    for i in 0..threshold - 2 {
+        // average of the next bucket, the third vertex of the triangles
+        let next_start = ((i + 1) as f64 * every) as usize + 1;
+        let next_end = (((i + 2) as f64 * every) as usize + 1).min(n);
+        let next_len = (next_end - next_start) as f64;
+        let avg_x = x[next_start..next_end].iter().sum::<f64>() / next_len;
+        let avg_y = y[next_start..next_end].iter().sum::<f64>() / next_len;
+        // the point of the current bucket with the largest triangle
+        let start = (i as f64 * every) as usize + 1;
+        let end = next_start;
+        let mut max_area = -1.;
+        let mut max_index = start;
+        for j in start..end {
+            let area = ((x[a] - avg_x) * (y[j] - y[a]) - (x[a] - x[j]) * (avg_y - y[a])).abs();
+            if area > max_area {
+                max_area = area;
+                max_index = j;
+            }
+        }
+        sampled.push(max_index);
+        a = max_index;
+    }
+    sampled.push(n - 1);
+    sampled
+}
+
+/// Ordinary least-squares fit of y = slope * x + intercept, return (slope, intercept).
+/// Pairs with a non-finite x or y are excluded from the fit.
+/// Return NANs when fewer than two valid pairs are left or all x are equal.
+pub fn linear_fit(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0f64, 0f64, 0f64, 0f64, 0f64);
+    x.iter()
+        .zip(y.iter())
+        .filter(|(xe, ye)| xe.is_finite() && ye.is_finite())
+        .for_each(|(xe, ye)| {
+            n += 1.;
+            sx += xe;
+            sy += ye;
+            sxx += xe * xe;
+            sxy += xe * ye;
+        });
+    let den = n * sxx - sx * sx;
+    if n < 2. || den == 0. {
+        return (f64::NAN, f64::NAN);
+    }
+    let slope = (n * sxy - sx * sy) / den;
+    let intercept = (sy - slope * sx) / n;
+    (slope, intercept)
+}
+
+/// Slope and intercept of the calibration through two points (raw reading, load in kg),
+/// e.g., the readings of the empty load cell and of a reference weight.
+/// Return NANs if the two raw readings are equal, as linear_fit.
+pub fn calibrate_from_points(p1: (f64, f64), p2: (f64, f64)) -> (f64, f64) {
+    linear_fit(&[p1.0, p2.0], &[p1.1, p2.1])
+}
 
 pub fn compare_f64_exact(a: f64, b: f64) -> bool {
     (a.is_nan() && b.is_nan()) || (a == b)
@@ -336,10 +1059,20 @@ pub fn compare_vecf64_approx(va: &[f64], vb: &[f64]) -> bool {
 /// This avoids random indexing-access the vector,
 /// which can reduce cache misses and the number of comparisons.
 /// This is used for removing bad datetimes and anomalies.
+/// Already sorted indices (e.g., from find_anomalies) are detected with a single scan
+/// and used directly, without the copy and sort.
 pub fn discharge_by_index<T: Copy>(ve: &[T], vi: &[usize]) -> Vec<T> {
+    if vi.is_empty() {
+        return ve.to_vec();
+    }
     let mut vout: Vec<T> = Vec::with_capacity(ve.len());
-    let mut vi = vi.to_vec();
-    vi.sort();
+    let vi: Cow<[usize]> = if vi.windows(2).all(|w| w[0] <= w[1]) {
+        Cow::Borrowed(vi)
+    } else {
+        let mut vi = vi.to_vec();
+        vi.sort();
+        Cow::Owned(vi)
+    };
     assert!(vi[vi.len() - 1usize] < ve.len());
     let mut vi_iter = vi.iter();
     let mut ve_iter = ve.iter().enumerate();
@@ -391,6 +1124,47 @@ pub fn setnan_by_index(ve: &mut [f64], vi: &[usize]) {
     }
 }
 
+/// Match a file name against a pattern with the wildcards * (any characters) and ? (one),
+/// e.g., "loads_*.csv" for the daily files.
+pub fn matches_wildcard(name: &str, pattern: &str) -> bool {
+    let n: Vec<char> = name.chars().collect();
+    let p: Vec<char> = pattern.chars().collect();
+    let (mut i, mut j) = (0usize, 0usize);
+    // pattern index after the last *, and name index it is matching from
+    let mut star: Option<(usize, usize)> = None;
+    while i < n.len() {
+        if (j < p.len()) && ((p[j] == '?') || (p[j] == n[i])) {
+            i += 1;
+            j += 1;
+        } else if (j < p.len()) && (p[j] == '*') {
+            star = Some((j + 1, i));
+            j += 1;
+        } else if let Some((sj, si)) = star {
+            // let the last * match one more character
+            star = Some((sj, si + 1));
+            i = si + 1;
+            j = sj;
+        } else {
+            return false;
+        }
+    }
+    p[j..].iter().all(|c| *c == '*')
+}
+
+/// Group sorted and unique indices into runs of consecutive indices,
+/// return the first and last index (inclusive) of each run.
+/// This merges the overlapping windows flagged by the anomaly detections.
+pub fn index_runs(vi: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for i in vi.iter() {
+        match runs.last_mut() {
+            Some((_, e)) if *i == *e + 1 => *e = *i,
+            _ => runs.push((*i, *i)),
+        }
+    }
+    runs
+}
+
 // An Error type for empty TimeLoad
 #[derive(Debug)]
 pub struct EmptyTimeLoad();
@@ -419,3 +1193,133 @@ impl fmt::Display for LenErr {
         )
     }
 }
+
+// An Error type for time and load vectors of different lengths,
+// which would be silently truncated by the zipped iterations.
+#[derive(Debug)]
+pub struct LenMismatch {
+    pub time_len: usize,
+    pub load_len: usize,
+}
+impl Error for LenMismatch {}
+impl fmt::Display for LenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Different lengths, {} datetimes and {} loads",
+            self.time_len, self.load_len
+        )
+    }
+}
+
+// An Error type for a missing csv column,
+// the line is the line number in the file, header included.
+#[derive(Debug)]
+pub struct MissingColumn {
+    pub column: String,
+    pub line: usize,
+}
+impl Error for MissingColumn {}
+impl fmt::Display for MissingColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Missing {} column at line {}", self.column, self.line)
+    }
+}
+
+// An Error type for the bad datetimes, either the file could not be read
+// or some lines could not be parsed, with the line number (from 1) and content of each of them.
+#[derive(Debug)]
+pub enum BadDatetimeParseError {
+    Io(std::io::Error),
+    Lines(Vec<(usize, String)>),
+}
+impl Error for BadDatetimeParseError {}
+impl fmt::Display for BadDatetimeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BadDatetimeParseError::Io(e) => write!(f, "Could not read the bad datetimes: {}", e),
+            BadDatetimeParseError::Lines(lines) => {
+                write!(f, "Could not parse {} bad datetimes:", lines.len())?;
+                for (n, l) in lines.iter() {
+                    write!(f, "\n  line {}: {}", n, l)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+impl From<std::io::Error> for BadDatetimeParseError {
+    fn from(e: std::io::Error) -> Self {
+        BadDatetimeParseError::Io(e)
+    }
+}
+
+// An Error type for an unordered time series,
+// the index is the one of the datetime that is not after the previous one.
+#[derive(Debug)]
+pub struct OrderError {
+    pub index: usize,
+    pub previous: DateTime<FixedOffset>,
+    pub next: DateTime<FixedOffset>,
+}
+impl Error for OrderError {}
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "time series is not ordered at index {}: {} <= {}",
+            self.index, self.next, self.previous
+        )
+    }
+}
+
+// An Error type for a non-continuous time series,
+// the index is the one of the datetime whose step differs from the first step.
+#[derive(Debug)]
+pub struct ContinuityError {
+    pub index: usize,
+    pub previous: DateTime<FixedOffset>,
+    pub next: DateTime<FixedOffset>,
+    pub expected_step: chrono::Duration,
+}
+impl Error for ContinuityError {}
+impl fmt::Display for ContinuityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "time series is not continuous at index {}: {} to {} is not {}",
+            self.index, self.previous, self.next, self.expected_step
+        )
+    }
+}
+
+// An Error type for a csv file that does not look like a load time series,
+// the line is the line number in the file (header included), if specific to a line.
+#[derive(Debug)]
+pub struct CsvError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+impl Error for CsvError {}
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(n) => write!(f, "invalid csv at line {}: {}", n, self.message),
+            None => write!(f, "invalid csv: {}", self.message),
+        }
+    }
+}
+
+// An Error type for a time window without any finite load,
+// e.g., the tare window of an interval removed as bad data.
+#[derive(Debug)]
+pub struct NoValidLoads {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+}
+impl Error for NoValidLoads {}
+impl fmt::Display for NoValidLoads {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no finite loads between {} and {}", self.start, self.end)
+    }
+}